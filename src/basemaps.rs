@@ -0,0 +1,364 @@
+use std::error::Error;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use crate::map::{self, OverworldMap};
+
+/// Where the five GR_BASE files are read from
+#[derive(Debug, PartialEq, Clone)]
+pub enum BaseMapSource {
+    /// A directory with GR_BASE1.BIN to GR_BASE5.BIN in it, like ./basemaps
+    Directory(String),
+    /// A disc image (.iso, or .bin/.cue), read from HYDLIDE/MAP01 on the disc
+    DiscImage(String),
+    /// The five files already in memory, GR_BASE1 first
+    Memory(Vec<Vec<u8>>),
+}
+
+impl Default for BaseMapSource {
+    fn default() -> Self {
+        BaseMapSource::Directory("./basemaps".to_string())
+    }
+}
+
+impl std::fmt::Display for BaseMapSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BaseMapSource::Directory(dir) => write!(f, "{}", dir),
+            BaseMapSource::DiscImage(path) => write!(f, "the disc image {}", path),
+            BaseMapSource::Memory(_) => write!(f, "memory"),
+        }
+    }
+}
+
+impl BaseMapSource {
+    /// The raw GR_BASE file for base map `n` (1-5)
+    pub fn read(&self, n: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+        match self {
+            BaseMapSource::Directory(dir) => {
+                let path = Path::new(dir).join(format!("GR_BASE{}.BIN", n));
+                Ok(std::fs::read(&path).map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?)
+            }
+            BaseMapSource::DiscImage(path) => crate::disc::DiscImage::open(path)?.read_file(&crate::disc::base_map_path(n)),
+            BaseMapSource::Memory(files) => {
+                let file = files.get((n as usize).wrapping_sub(1)).ok_or(format!("No GR_BASE{} in memory", n))?;
+                Ok(file.clone())
+            }
+        }
+    }
+
+    /// Reads and parses base map `n`
+    pub fn load(&self, n: u32) -> Result<OverworldMap, Box<dyn Error>> {
+        map::load_core_map_from_vec(&self.read(n)?)
+    }
+}
+
+/// All five base maps in all four rotations, parsed and rotated once up front. Nothing changes
+/// them after that, so every generation thread can share one set instead of rereading and
+/// rotating the files for each map.
+pub struct BaseMapSet {
+    // Base map n rotated r times is at (n - 1) * 4 + r
+    variants: Vec<OverworldMap>,
+    /// Made by someone rather than read from the game, so there's no telling whether every
+    /// feature fits. Generation gives up on a map after so many tries on these.
+    pub custom: bool,
+}
+
+impl BaseMapSet {
+    /// Builds the set from the five unrotated base maps, GR_BASE1 first
+    pub fn new(maps: Vec<OverworldMap>) -> Result<BaseMapSet, Box<dyn Error>> {
+        if maps.len() != 5 {
+            return Err(format!("Need 5 base maps, got {}", maps.len()).into());
+        }
+        let mut variants = Vec::with_capacity(20);
+        for map in maps {
+            for rotation in 0..4 {
+                let mut variant = map.clone();
+                variant.rotate(rotation);
+                variants.push(variant);
+            }
+        }
+        Ok(BaseMapSet { variants, custom: false })
+    }
+
+    pub fn load(source: &BaseMapSource) -> Result<BaseMapSet, Box<dyn Error>> {
+        BaseMapSet::new((1..6).map(|n| source.load(n)).collect::<Result<_, _>>()?)
+    }
+
+    /// Base map `n` (1-5) rotated `rotation` (0-3) times, the way from_code rolls them
+    pub fn get(&self, n: u32, rotation: u8) -> &OverworldMap {
+        &self.variants[(n as usize - 1) * 4 + rotation as usize]
+    }
+
+    /// Every ((base map, rotation), map) pair in order
+    pub fn variants(&self) -> impl Iterator<Item = ((u32, u8), &OverworldMap)> {
+        self.variants.iter().enumerate().map(|(i, map)| ((i as u32 / 4 + 1, (i % 4) as u8), map))
+    }
+}
+
+// None until something's set, meaning the default ./basemaps
+static SOURCE: Mutex<Option<Arc<BaseMapSource>>> = Mutex::new(None);
+// Loaded from SOURCE the first time it's needed
+static BASE_MAPS: Mutex<Option<Arc<BaseMapSet>>> = Mutex::new(None);
+
+/// Where load_base_map (and so from_code) currently gets its base maps
+pub fn source() -> Arc<BaseMapSource> {
+    SOURCE.lock().unwrap().get_or_insert_with(|| Arc::new(BaseMapSource::default())).clone()
+}
+
+/// The base maps from_code builds on, shared by every thread
+pub fn base_maps() -> Result<Arc<BaseMapSet>, Box<dyn Error>> {
+    let mut base_maps = BASE_MAPS.lock().unwrap();
+    if let Some(set) = base_maps.as_ref() {
+        return Ok(set.clone());
+    }
+    let set = Arc::new(BaseMapSet::load(&source())?);
+    *base_maps = Some(set.clone());
+    Ok(set)
+}
+
+/// Switches every thread over to reading base maps from `source`, after checking all five load
+pub fn set_source(source: BaseMapSource) -> Result<bool, Box<dyn Error>> {
+    let set = BaseMapSet::load(&source)?;
+    let mut base_maps = BASE_MAPS.lock().unwrap();
+    *SOURCE.lock().unwrap() = Some(Arc::new(source));
+    *base_maps = Some(Arc::new(set));
+    Ok(true)
+}
+
+/// Checks a base map (unrotated) has what generation relies on: a castle tablet (0x12) for the
+/// route to end at, mountain edge (0x1b) for the sealed dungeon to spawn on, and a 2x2 patch of
+/// empty or rock tiles the ruins can go on. The retail maps all do, this is for custom ones.
+/// Passing doesn't promise every seed fits, features also need to be 5 tiles apart.
+pub fn validate_base_map(map: &OverworldMap) -> Result<bool, Box<dyn Error>> {
+    let (width, height) = (map.width(), map.height());
+    if (width, height) != (50, 50) {
+        return Err(format!("Base map is {}x{}, needs to be 50x50", width, height).into());
+    }
+    let id = |x: usize, y: usize| map.tiles()[x % width + (y % height) * width].id;
+    let mut problems = Vec::new();
+    if map.find_tile(0x12).is_none() {
+        problems.push("no castle tablet (0x12)");
+    }
+    if map.find_tile(0x1b).is_none() {
+        problems.push("no mountain edge (0x1b) for the sealed dungeon");
+    }
+    let ruins_fit = (0..height).any(|y| (0..width).any(|x| {
+        [(0, 0), (1, 0), (0, 1), (1, 1)].iter().all(|(dx, dy)| matches!(id(x + dx, y + dy), 0 | 5))
+    }));
+    if !ruins_fit {
+        problems.push("no 2x2 patch of empty or rock tiles for the ruins");
+    }
+    if !problems.is_empty() {
+        return Err(problems.join(", ").into());
+    }
+    Ok(true)
+}
+
+/// Like set_source, but for a custom set of base maps rather than the retail ones, so each map is
+/// checked with validate_base_map first.
+pub fn set_custom_source(source: BaseMapSource) -> Result<bool, Box<dyn Error>> {
+    let set = BaseMapSet { custom: true, ..BaseMapSet::load(&source)? };
+    for ((n, _), map) in set.variants().filter(|((_, rotation), _)| *rotation == 0) {
+        validate_base_map(map).map_err(|e| format!("GR_BASE{}: {}", n, e))?;
+    }
+    let mut base_maps = BASE_MAPS.lock().unwrap();
+    *SOURCE.lock().unwrap() = Some(Arc::new(source));
+    *base_maps = Some(Arc::new(set));
+    Ok(true)
+}
+
+/// A GR_BASE file as shipped on one release of the game
+pub struct KnownBaseMap {
+    pub n: u32,
+    pub release: &'static str,
+    pub crc32: u32,
+    // Whether maps generated on this release have been checked against the game. Files from other
+    // releases are recognised but warned about.
+    pub supported: bool,
+}
+
+/// Checksums of the retail base maps, per release. Nobody has recorded them from a real disc yet,
+/// so this is empty and every valid file is reported as unverified. Menu option 16 prints the
+/// CRC32 of each file you have, add a line per file and release once it's been confirmed.
+pub const RETAIL_BASE_MAPS: &[KnownBaseMap] = &[];
+
+/// CRC-32 (the zip/PNG one), to match what most checksum tools print for the files
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum BaseMapStatus {
+    /// Couldn't be read at all
+    Missing(String),
+    /// Read, but it isn't a base map file (wrong size or header)
+    Invalid(String),
+    /// Matches the retail file for this slot
+    Retail { release: &'static str, supported: bool },
+    /// Matches a retail file, but for a different base map, e.g. GR_BASE2 copied over GR_BASE3
+    Misplaced { actually: u32, release: &'static str },
+    /// A valid base map that doesn't match any known release of this slot
+    Modified { crc32: u32 },
+    /// A valid base map, but there's nothing known to compare it with
+    Unverified { crc32: u32 },
+}
+
+impl BaseMapStatus {
+    /// Whether this is worth warning about before generating anything
+    pub fn is_problem(&self) -> bool {
+        match self {
+            BaseMapStatus::Retail { supported, .. } => !supported,
+            BaseMapStatus::Unverified { .. } => false,
+            _ => true,
+        }
+    }
+
+    pub fn describe(&self, n: u32) -> String {
+        match self {
+            BaseMapStatus::Missing(e) => format!("GR_BASE{} is missing: {}", n, e),
+            BaseMapStatus::Invalid(e) => format!("GR_BASE{} isn't a base map: {}", n, e),
+            BaseMapStatus::Retail { release, supported: true } => format!("GR_BASE{} is from the {} disc", n, release),
+            BaseMapStatus::Retail { release, supported: false } =>
+                format!("GR_BASE{} is from the {} disc, which generated maps haven't been checked against", n, release),
+            BaseMapStatus::Misplaced { actually, release } => format!("GR_BASE{} is really GR_BASE{} from the {} disc", n, actually, release),
+            BaseMapStatus::Modified { crc32 } => format!("GR_BASE{} has been modified (crc32 {:08x}), maps won't match the game", n, crc32),
+            BaseMapStatus::Unverified { crc32 } => format!("GR_BASE{} has crc32 {:08x}, no known checksum to compare it with", n, crc32),
+        }
+    }
+}
+
+/// Works out what the file in base map slot `n` is by comparing it with the `known` files
+pub fn identify(n: u32, file: &[u8], known: &[KnownBaseMap]) -> BaseMapStatus {
+    if let Err(e) = map::map_validity_check(file) {
+        return BaseMapStatus::Invalid(e.to_string());
+    }
+    let crc32 = crc32(file);
+    let matches: Vec<&KnownBaseMap> = known.iter().filter(|k| k.crc32 == crc32).collect();
+    if let Some(k) = matches.iter().find(|k| k.n == n) {
+        return BaseMapStatus::Retail { release: k.release, supported: k.supported };
+    }
+    if let Some(k) = matches.first() {
+        return BaseMapStatus::Misplaced { actually: k.n, release: k.release };
+    }
+    if known.iter().any(|k| k.n == n) {
+        return BaseMapStatus::Modified { crc32 };
+    }
+    BaseMapStatus::Unverified { crc32 }
+}
+
+/// Identifies all five base maps wherever load_base_map is currently reading them from
+pub fn check_base_maps() -> Vec<(u32, BaseMapStatus)> {
+    let source = source();
+    (1..6).map(|n| {
+        let status = match source.read(n) {
+            Ok(file) => identify(n, &file, RETAIL_BASE_MAPS),
+            Err(e) => BaseMapStatus::Missing(e.to_string()),
+        };
+        (n, status)
+    }).collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::map::{_FCargs, Difficulty};
+    use crate::fcargs;
+
+    fn base_file(fill: u8) -> Vec<u8> {
+        let mut file = vec![fill; 0x23c4];
+        file[0x660..0x678].copy_from_slice(&map::expected_map_header());
+        file
+    }
+
+    /// A made up base map that generation can work with: empty apart from a row of mountain
+    /// edge for the sealed dungeon and a castle tablet
+    pub(crate) fn synthetic_base_file() -> Vec<u8> {
+        let mut file = base_file(0);
+        for x in 0..50 {
+            file[0x678 + (x + 10*50)*3] = 0x1b;
+        }
+        file[0x678 + (25 + 25*50)*3] = 0x12;
+        file
+    }
+
+    #[test]
+    fn generates_from_memory() {
+        let source = BaseMapSource::Memory(vec![synthetic_base_file(); 5]);
+        assert!(source.read(6).is_err() && source.read(0).is_err());
+        let set = Arc::new(BaseMapSet::load(&source).unwrap());
+        let fc = _FCargs { base_maps: Some(set.clone()), ..fcargs!("FNMCNTLGHF", Difficulty::Easy) };
+        let map = OverworldMap::from_code(&fc).unwrap();
+        assert!(map.find_tile(0x12).is_some() && map.find_tile(0xff).is_some());
+        assert!(map.tiles() == OverworldMap::from_code(&fc).unwrap().tiles());
+
+        let info = map.info();
+        assert!(set.get(info.base_map, info.base_rotation).find_tile(0x12) == map.find_tile(0x12));
+        assert!(set.variants().map(|(v, _)| v).nth(13) == Some((4, 1)));
+
+        let broken = BaseMapSource::Memory(vec![synthetic_base_file(), vec![0; 10]]);
+        assert!(broken.load(1).is_ok() && broken.load(2).is_err());
+        assert!(set_source(broken).is_err());
+    }
+
+    #[test]
+    fn validates_custom_maps() {
+        let load = |file: Vec<u8>| map::load_core_map_from_vec(&file).unwrap();
+        let good = load(synthetic_base_file());
+        assert!(validate_base_map(&good).is_ok());
+
+        let mut no_castle = synthetic_base_file();
+        no_castle[0x678 + (25 + 25*50)*3] = 0;
+        let error = validate_base_map(&load(no_castle)).unwrap_err().to_string();
+        assert!(error.contains("castle") && !error.contains("sealed"));
+
+        // Everything filled in with edge but a checkerboard, so no 2x2 patch fits the ruins
+        let mut crowded = synthetic_base_file();
+        for i in 0..50*50 {
+            if (i % 50 + i / 50) % 2 == 0 { crowded[0x678 + i*3] = 0x1b; }
+        }
+        assert!(validate_base_map(&load(crowded)).unwrap_err().to_string().contains("ruins"));
+
+        // Without edge the sealed dungeon can never be placed, which has to fail rather than hang
+        let mut no_edge = synthetic_base_file();
+        for x in 0..50 { no_edge[0x678 + (x + 10*50)*3] = 0; }
+        let set = Arc::new(BaseMapSet { custom: true, ..BaseMapSet::new(vec![load(no_edge); 5]).unwrap() });
+        let fc = _FCargs { base_maps: Some(set), ..fcargs!("FNMCNTLGHF", Difficulty::Easy) };
+        assert!(OverworldMap::from_code(&fc).is_err());
+    }
+
+    #[test]
+    fn identifies_files() {
+        assert!(crc32(b"123456789") == 0xcbf43926);
+
+        let (a, b, c) = (base_file(1), base_file(2), base_file(3));
+        let known = [
+            KnownBaseMap { n: 1, release: "North American", crc32: crc32(&a), supported: true },
+            KnownBaseMap { n: 2, release: "North American", crc32: crc32(&b), supported: true },
+            KnownBaseMap { n: 2, release: "Japanese", crc32: crc32(&c), supported: false },
+        ];
+        assert!(identify(1, &a, &known) == BaseMapStatus::Retail { release: "North American", supported: true });
+        assert!(!identify(1, &a, &known).is_problem());
+        let japanese = identify(2, &c, &known);
+        assert!(japanese == BaseMapStatus::Retail { release: "Japanese", supported: false } && japanese.is_problem());
+        assert!(identify(3, &b, &known) == BaseMapStatus::Misplaced { actually: 2, release: "North American" });
+
+        let mut changed = a.clone();
+        changed[0x1000] ^= 1;
+        assert!(identify(1, &changed, &known) == BaseMapStatus::Modified { crc32: crc32(&changed) });
+        assert!(identify(4, &changed, &known) == BaseMapStatus::Unverified { crc32: crc32(&changed) });
+        assert!(!identify(4, &changed, &known).is_problem());
+
+        assert!(matches!(identify(1, &a[..100], &known), BaseMapStatus::Invalid(_)));
+        let mut bad_header = a.clone();
+        bad_header[0x660] ^= 1;
+        assert!(identify(1, &bad_header, &known).is_problem());
+        assert!(BaseMapStatus::Missing("gone".to_string()).is_problem());
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::Once;
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::filter::{Expression, Filter};
+use crate::json::{self, Value};
+use crate::leaderboard::{Leaderboard, Metric};
+use crate::map;
+use crate::random::VHRandom;
+use crate::sweep::{RecordFormat, SeedRange, SweepConfig, SweepProgress};
+
+const CHECKPOINT_FORMAT_VERSION: u64 = 1;
+
+/// Where a records file had got to when a checkpoint was written
+#[derive(Debug, PartialEq, Clone)]
+pub struct RecordsState {
+    pub format: RecordFormat,
+    /// None when the records were going to stdout
+    pub path: Option<String>,
+    /// Bytes written, anything after this is from after the checkpoint
+    pub length: u64,
+}
+
+/// Everything needed to carry on a sweep that was stopped: what it was sweeping, how far it got
+/// and where its records were going
+#[derive(Debug, PartialEq, Clone)]
+pub struct Checkpoint {
+    pub config: SweepConfig,
+    pub progress: SweepProgress,
+    pub records: Option<RecordsState>,
+}
+
+impl Checkpoint {
+    pub fn to_json(&self) -> Result<String, Box<dyn Error>> {
+        let mut o = BTreeMap::new();
+        o.insert("format".to_string(), CHECKPOINT_FORMAT_VERSION.into());
+        o.insert("start".to_string(), (self.config.range.start as u64).into());
+        o.insert("count".to_string(), self.config.range.count.into());
+        o.insert("difficulty".to_string(), map::difficulty_text(&self.config.difficulty).into());
+        o.insert("filter".to_string(), self.config.filter.as_ref().map_or(Value::Null, |f| f.to_string().as_str().into()));
+        o.insert("save_dir".to_string(), self.config.save_dir.as_deref().map_or(Value::Null, Value::from));
+        o.insert("top".to_string(), self.config.top.map_or(Value::Null, |(metric, size)| {
+            let mut top = BTreeMap::new();
+            top.insert("metric".to_string(), metric.name().into());
+            top.insert("size".to_string(), size.into());
+            Value::Object(top)
+        }));
+        o.insert("leaderboard".to_string(), self.progress.leaderboard.to_json_value());
+        o.insert("database".to_string(), self.config.database.as_deref().map_or(Value::Null, Value::from));
+        o.insert("done".to_string(), self.progress.done.into());
+        o.insert("kept".to_string(), self.progress.kept.into());
+        o.insert("rejected".to_string(), Value::Array(self.progress.rejected.iter().map(|&n| n.into()).collect()));
+        o.insert("failed".to_string(), self.progress.failed.into());
+        o.insert("best".to_string(), self.progress.best.map_or(Value::Null, |(seed, length)| {
+            let mut best = BTreeMap::new();
+            best.insert("seed".to_string(), (seed as u64).into());
+            // Not read back, it's there for anyone looking at the file
+            best.insert("code".to_string(), VHRandom::from_seed(seed).get_code().as_str().into());
+            best.insert("length".to_string(), (length as u64).into());
+            Value::Object(best)
+        }));
+        o.insert("records".to_string(), self.records.as_ref().map_or(Value::Null, |r| {
+            let mut records = BTreeMap::new();
+            records.insert("format".to_string(), r.format.name().into());
+            records.insert("path".to_string(), r.path.as_deref().map_or(Value::Null, Value::from));
+            records.insert("length".to_string(), r.length.into());
+            Value::Object(records)
+        }));
+        Value::Object(o).to_json()
+    }
+
+    pub fn from_json(text: &str) -> Result<Checkpoint, Box<dyn Error>> {
+        let v = json::parse(text)?;
+        let format = v.get("format").and_then(Value::as_u64).ok_or("Checkpoint has no format version")?;
+        if format > CHECKPOINT_FORMAT_VERSION {
+            return Err(format!("Checkpoint format {} is newer than this program understands", format).into());
+        }
+        let field = |v: &Value, name: &str| -> Result<Value, Box<dyn Error>> {
+            Ok(v.get(name).cloned().ok_or(format!("Checkpoint is missing '{}'", name))?)
+        };
+        let number = |v: &Value, name: &str| -> Result<u64, Box<dyn Error>> {
+            Ok(field(v, name)?.as_u64().ok_or(format!("Checkpoint '{}' is not a number", name))?)
+        };
+        let text = |v: &Value, name: &str| -> Result<Option<String>, Box<dyn Error>> {
+            match field(v, name)? {
+                Value::Null => Ok(None),
+                Value::String(s) => Ok(Some(s)),
+                _ => Err(format!("Checkpoint '{}' is not a string", name).into()),
+            }
+        };
+
+        let start = u32::try_from(number(&v, "start")?).map_err(|_| "Checkpoint 'start' is not a 32 bit seed")?;
+        let range = SeedRange::new(start, number(&v, "count")?)?;
+        let filter = match field(&v, "filter")? {
+            Value::Null => None,
+            Value::String(f) => Some(Expression::parse(&f)?),
+            _ => return Err("Checkpoint 'filter' is not an expression".into()),
+        };
+        let config = SweepConfig {
+            range,
+            difficulty: map::text_difficulty(&text(&v, "difficulty")?.unwrap_or_default()),
+            filter,
+            save_dir: text(&v, "save_dir")?,
+            top: match field(&v, "top")? {
+                Value::Null => None,
+                top => Some((Metric::parse(&text(&top, "metric")?.unwrap_or_default())?, number(&top, "size")? as usize)),
+            },
+            database: text(&v, "database")?,
+        };
+        let done = number(&v, "done")?;
+        if done > range.count {
+            return Err("Checkpoint has more seeds done than are in its range".into());
+        }
+        let best = match field(&v, "best")? {
+            Value::Null => None,
+            best => Some((number(&best, "seed")? as u32, number(&best, "length")? as u16)),
+        };
+        let parts = config.filter.as_ref().map_or(0, |f| f.parts().len());
+        let rejected = field(&v, "rejected")?.as_array().ok_or("Checkpoint 'rejected' should be an array")?
+            .iter().map(|n| n.as_u64().ok_or("Checkpoint 'rejected' should be counts"))
+            .collect::<Result<Vec<u64>, _>>()?;
+        if rejected.len() != parts {
+            return Err(format!("Checkpoint 'rejected' should have a count for each of the {} parts of the filter", parts).into());
+        }
+        let size = config.top.map_or(0, |(_, size)| size);
+        let leaderboard = Leaderboard::from_json_value(size, &field(&v, "leaderboard")?)?;
+        let progress = SweepProgress { done, kept: number(&v, "kept")?, best, rejected, failed: number(&v, "failed")?, leaderboard };
+        let records = match field(&v, "records")? {
+            Value::Null => None,
+            r => Some(RecordsState {
+                format: RecordFormat::parse(&text(&r, "format")?.unwrap_or_default())?,
+                path: text(&r, "path")?,
+                length: number(&r, "length")?,
+            }),
+        };
+        Ok(Checkpoint { config, progress, records })
+    }
+
+    /// Writes the checkpoint next to `path` and renames it over, so a crash halfway through
+    /// writing never leaves a broken checkpoint behind
+    pub fn save(&self, path: &str) -> Result<bool, Box<dyn Error>> {
+        let temp = format!("{}.tmp", path);
+        std::fs::write(&temp, self.to_json()? + "\n")?;
+        std::fs::rename(&temp, path)?;
+        Ok(true)
+    }
+
+    pub fn load(path: &str) -> Result<Checkpoint, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Can't read checkpoint {}: {}", path, e))?;
+        Checkpoint::from_json(&text)
+    }
+}
+
+// Set by the Ctrl-C handler, checked by the sweep between chunks
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+// Whether Ctrl-C should set INTERRUPTED at all, rather than ending the program
+static CATCHING: AtomicBool = AtomicBool::new(false);
+static HANDLER: Once = Once::new();
+
+/// True once Ctrl-C has been pressed since catch_interrupts
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+// ctrlc only lets a program set its handler once, so it stays installed and CATCHING decides what
+// it does. The first Ctrl-C while catching just sets the flag so the sweep can write a checkpoint
+// and stop; a second one, or any outside a sweep, ends the program as usual in case it's stuck.
+fn on_interrupt() {
+    if !CATCHING.load(Ordering::SeqCst) || INTERRUPTED.swap(true, Ordering::SeqCst) {
+        std::process::exit(130);
+    }
+}
+
+/// Makes Ctrl-C set the interrupted flag instead of ending the program
+pub fn catch_interrupts() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+    CATCHING.store(true, Ordering::SeqCst);
+    HANDLER.call_once(|| {
+        if let Err(e) = ctrlc::set_handler(on_interrupt) {
+            eprintln!("Couldn't catch Ctrl-C, it will stop the sweep without a checkpoint: {}", e);
+        }
+    });
+}
+
+/// Puts Ctrl-C back to ending the program
+pub fn release_interrupts() {
+    CATCHING.store(false, Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::leaderboard::Entry;
+    use crate::map::Difficulty;
+
+    #[test]
+    fn round_trips_checkpoints() {
+        let checkpoint = Checkpoint {
+            config: SweepConfig {
+                range: SeedRange::new(u32::MAX - 5, 1 << 32).unwrap(),
+                difficulty: Difficulty::Hard,
+                filter: Expression::parse_filter("map4,under50").unwrap(),
+                save_dir: Some("./genmaps".to_string()),
+                top: Some((Metric::LastHalf, 2)),
+                database: Some("seeds.db".to_string()),
+            },
+            progress: SweepProgress { done: 12288, kept: 3, best: Some((77, 41)), rejected: vec![9000, 3284], failed: 1, leaderboard: Leaderboard::new(2) },
+            records: Some(RecordsState { format: RecordFormat::Csv, path: Some("out.csv".to_string()), length: 900 }),
+        };
+        let mut checkpoint = checkpoint;
+        checkpoint.progress.leaderboard.offer(Entry { score: 3, seed: 77, first_half: 38, last_half: 3, base_map: 4 });
+        assert!(Checkpoint::from_json(&checkpoint.to_json().unwrap()).unwrap() == checkpoint);
+
+        let mut bare = Checkpoint { progress: SweepProgress { rejected: vec![0, 0], ..Default::default() }, records: None, ..checkpoint.clone() };
+        bare.config.top = None;
+        assert!(Checkpoint::from_json(&bare.to_json().unwrap()).unwrap() == bare);
+
+        let too_far = Checkpoint { progress: SweepProgress { done: (1 << 32) + 1, ..Default::default() }, ..checkpoint.clone() };
+        assert!(Checkpoint::from_json(&too_far.to_json().unwrap()).is_err());
+        assert!(Checkpoint::from_json("{\"format\": 99}").is_err());
+
+        // Every field has to be there, and filters have to be expressions
+        let Value::Object(full) = json::parse(&checkpoint.to_json().unwrap()).unwrap() else { panic!() };
+        for name in ["rejected", "failed", "top", "leaderboard", "database", "filter"] {
+            let mut missing = full.clone();
+            missing.remove(name);
+            assert!(Checkpoint::from_json(&Value::Object(missing).to_json().unwrap()).is_err(), "{}", name);
+        }
+        let mut flags = full.clone();
+        flags.insert("filter".to_string(), Value::Array(vec![Value::Bool(true), Value::Bool(false), Value::Bool(true)]));
+        assert!(Checkpoint::from_json(&Value::Object(flags).to_json().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Write;
+use crate::basemaps::{self, BaseMapSource};
+use crate::json::Value;
+use crate::index::Query;
+use crate::leaderboard::{Leaderboard, Metric};
+use crate::merge;
+use crate::random::VHRandom;
+use crate::results::{self, ResultsDb};
+use crate::filter::Expression;
+use crate::map::{self, _FCargs, Difficulty, OverworldMap};
+use crate::sweep::{self, default_threads, RecordFormat, RecordWriter, SeedRange, SweepConfig};
+use crate::fcargs;
+
+pub const USAGE: &str = "\
+Usage: vh_seed_generator [command] [options]
+
+Commands:
+  menu                 The interactive menu (what you get with no command)
+  gen <code>           Generate a map. --format ascii (default), json, tmx or mednafen,
+                       --output <file> to write it somewhere other than stdout
+  bases                Print the five base maps in all four rotations. --format ascii or json
+  sweep                Generate a range of seeds: --start <seed> (default 0), --count <n>
+                       (default all 2^32, wrapping around to 0), --threads <n> (default all cores),
+                       --filter 'base == 4 && dist(@, V) < 8' (or map4,last3,under50) to
+                       only keep some, --save to save them,
+                       --records csv or jsonl to print a line for each one kept (to
+                       --output <file> if it's given), --checkpoint <file> to save progress
+                       to so Ctrl-C stops it cleanly, --resume <file> to carry on from one
+                       (with the settings it was started with, apart from --threads).
+                       Progress is printed to stderr every 10 seconds unless --quiet.
+                       --top <n> keeps a leaderboard of the n best maps by --metric total
+                       (default), first or last, printed at the end (--leaderboard <file> to
+                       write it to a file instead, --format text or json). --database <file>
+                       writes a results database of every seed (not with --filter).
+                       --shard 3/16 sweeps the third of 16 equal slices of the range, for
+                       splitting it across machines (needs --checkpoint)
+  merge <checkpoint...>
+                       Put the checkpoints of a sharded sweep back together, checking they
+                       cover the range with no gaps or overlaps. Prints the combined
+                       leaderboard like sweep does. --output <file> joins their records files,
+                       --database <file> their results databases, --checkpoint <file> saves
+                       the merged checkpoint
+  db <file> [seed...]  Describe a results database, or look seeds (numbers or codes) up in it
+  query <file> <terms> Print every seed in a results database that matches all the terms, the
+                       ones menu option 13 takes plus first=, last= and total= for the route,
+                       with ~ or <= and >=. There's no index, it reads the whole file, so
+                       it's slow on big databases. --limit <n> to stop after n
+  rank [dir]           Rank the maps saved in dir (default the --genmaps directory) by route
+                       length. --format text or json
+  explain <code>       Show a code's seed, base map, feature positions and route. --format
+                       text or json
+  help                 This
+
+Options for every command:
+  --difficulty <d>     Easy (default), Medium, Hard or PRO
+  --basemaps <path>    Directory or disc image to read the base maps from (default ./basemaps)
+  --custom-basemaps <dir>
+                       Generate on your own base maps instead, see menu option 18
+  --genmaps <dir>      Directory maps are saved to and ranked from (default ./genmaps)
+";
+
+// Flags that don't take a value
+const SWITCHES: &[&str] = &["save", "quiet"];
+
+// Flags every command takes, then the ones each command takes on top
+const COMMON_FLAGS: &[&str] = &["difficulty", "basemaps", "custom-basemaps", "genmaps"];
+const COMMAND_FLAGS: &[(&str, &[&str])] = &[
+    ("menu", &[]),
+    ("help", &[]),
+    ("gen", &["format", "output"]),
+    ("bases", &["format"]),
+    ("sweep", &["start", "count", "threads", "filter", "save", "records", "output", "checkpoint", "resume",
+                "quiet", "top", "metric", "leaderboard", "format", "database", "shard"]),
+    ("merge", &["output", "database", "checkpoint", "leaderboard", "format"]),
+    ("db", &[]),
+    ("query", &["limit"]),
+    ("rank", &["format"]),
+    ("explain", &["format"]),
+];
+
+/// The command line split into positional arguments and --flags
+#[derive(Debug, PartialEq)]
+struct Args {
+    positional: Vec<String>,
+    flags: HashMap<String, String>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Args, Box<dyn Error>> {
+        let mut positional = Vec::new();
+        let mut flags = HashMap::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let Some(flag) = arg.strip_prefix("--") else {
+                positional.push(arg.clone());
+                continue;
+            };
+            let (name, value) = match flag.split_once('=') {
+                Some((name, value)) => (name.to_string(), value.to_string()),
+                None if SWITCHES.contains(&flag) => (flag.to_string(), String::new()),
+                None => (flag.to_string(), args.next().ok_or(format!("--{} needs a value", flag))?.clone()),
+            };
+            flags.insert(name, value);
+        }
+        Ok(Args { positional, flags })
+    }
+
+    /// Refuses any flag the command doesn't know, rather than quietly ignoring a typo like
+    /// --checkpiont and running without it. Unknown commands are left for run to report.
+    fn check_flags(&self, command: &str) -> Result<bool, Box<dyn Error>> {
+        let Some((_, known)) = COMMAND_FLAGS.iter().find(|(c, _)| *c == command) else { return Ok(false) };
+        let mut names: Vec<&String> = self.flags.keys().collect();
+        names.sort();
+        match names.into_iter().find(|n| !COMMON_FLAGS.contains(&n.as_str()) && !known.contains(&n.as_str())) {
+            Some(name) => Err(format!("{} doesn't take --{}\n\n{}", command, name, USAGE).into()),
+            None => Ok(true),
+        }
+    }
+
+    fn flag(&self, name: &str) -> Option<&str> {
+        self.flags.get(name).map(|v| v.as_str())
+    }
+
+    fn number(&self, name: &str, default: u64) -> Result<u64, Box<dyn Error>> {
+        match self.flag(name) {
+            Some(v) => Ok(v.parse().map_err(|_| format!("--{} should be a number, not '{}'", name, v))?),
+            None => Ok(default),
+        }
+    }
+
+    fn format(&self, allowed: &[&str]) -> Result<String, Box<dyn Error>> {
+        let format = self.flag("format").unwrap_or(allowed[0]);
+        if !allowed.contains(&format) {
+            return Err(format!("--format should be one of {}", allowed.join(", ")).into());
+        }
+        Ok(format.to_string())
+    }
+
+    /// The nth positional argument after the command
+    fn argument(&self, n: usize, what: &str) -> Result<&str, Box<dyn Error>> {
+        self.positional.get(n + 1).map(|v| v.as_str()).ok_or(format!("Missing {}, see help", what).into())
+    }
+}
+
+fn parse_difficulty(s: &str) -> Result<Difficulty, Box<dyn Error>> {
+    ["Easy", "Medium", "Hard", "PRO"].iter()
+        .find(|d| d.eq_ignore_ascii_case(s))
+        .map(|d| map::text_difficulty(d))
+        .ok_or(format!("Unknown difficulty '{}', it's Easy, Medium, Hard or PRO", s).into())
+}
+
+// "3/16" for the third of 16 shards
+fn parse_shard(s: &str) -> Result<(u64, u64), Box<dyn Error>> {
+    let bad = || format!("--shard should look like 3/16, not '{}'", s);
+    let (k, n) = s.split_once('/').ok_or_else(bad)?;
+    Ok((k.parse().map_err(|_| bad())?, n.parse().map_err(|_| bad())?))
+}
+
+
+// Writes to --output if it's given, otherwise stdout
+fn write_output(args: &Args, bytes: &[u8]) -> Result<bool, Box<dyn Error>> {
+    match args.flag("output") {
+        Some(path) => std::fs::write(path, bytes)?,
+        None => std::io::stdout().write_all(bytes)?,
+    }
+    Ok(true)
+}
+
+/// Runs a command line (without the program name). Errors are returned for main to print
+/// instead of panicking, so scripts get a message and a failing exit code.
+pub fn run(args: &[String]) -> Result<bool, Box<dyn Error>> {
+    let args = Args::parse(args)?;
+    let command = args.positional.first().map(|c| c.as_str()).unwrap_or("menu");
+    args.check_flags(command)?;
+    let difficulty = parse_difficulty(args.flag("difficulty").unwrap_or("Easy"))?;
+    let output_dir = args.flag("genmaps").unwrap_or("./genmaps").to_string();
+    if let Some(path) = args.flag("basemaps") {
+        let source = if std::path::Path::new(path).is_dir() {
+            BaseMapSource::Directory(path.to_string())
+        } else {
+            BaseMapSource::DiscImage(path.to_string())
+        };
+        basemaps::set_source(source)?;
+    }
+    if let Some(dir) = args.flag("custom-basemaps") {
+        basemaps::set_custom_source(BaseMapSource::Directory(dir.to_string()))?;
+    }
+
+    if matches!(command, "gen" | "sweep" | "explain") && args.flag("custom-basemaps").is_none() {
+        for (n, status) in basemaps::check_base_maps() {
+            if status.is_problem() {
+                eprintln!("Warning: {}", status.describe(n));
+            }
+        }
+    }
+
+    match command {
+        "menu" => crate::menu(difficulty, output_dir),
+        "help" => print!("{}", USAGE),
+        "gen" => {
+            let map = OverworldMap::from_code(&fcargs!(args.argument(0, "the code")?, difficulty))?;
+            match args.format(&["ascii", "json", "tmx", "mednafen"])?.as_str() {
+                "json" => write_output(&args, (map.to_json()? + "\n").as_bytes())?,
+                "tmx" => write_output(&args, map.to_tmx().as_bytes())?,
+                "mednafen" => write_output(&args, &map.to_mednafen_bytes())?,
+                _ => write_output(&args, map.route_ascii(&map.shortest_route()).as_bytes())?,
+            };
+        }
+        "bases" => {
+            let set = basemaps::base_maps()?;
+            match args.format(&["ascii", "json"])?.as_str() {
+                "json" => {
+                    let maps = set.variants().filter(|((_, rotation), _)| *rotation == 0).map(|(_, m)| m.to_json_value()).collect();
+                    println!("{}", Value::Array(maps).to_json()?);
+                }
+                _ => for ((n, rotation), base) in set.variants() {
+                    println!("GR_BASE{} rotation {}", n, rotation);
+                    base.print_map();
+                    println!("--------------------------------------------------");
+                },
+            }
+        }
+        "sweep" if args.flag("resume").is_some() => {
+            let path = args.flag("resume").unwrap_or_default();
+            let threads = args.number("threads", default_threads() as u64)? as usize;
+            let (config, stats) = sweep::resume_sweep(path, threads, args.flag("quiet").is_none())?;
+            eprintln!("{}", sweep_summary(&config, &stats, path));
+            write_leaderboard(&args, &config, &stats.progress.leaderboard)?;
+        }
+        "sweep" => {
+            let start = u32::try_from(args.number("start", 0)?).map_err(|_| "--start has to be a 32 bit seed")?;
+            let mut range = SeedRange::new(start, args.number("count", 1 << 32)?)?;
+            if let Some(shard) = args.flag("shard") {
+                let (k, n) = parse_shard(shard)?;
+                if args.flag("checkpoint").is_none() {
+                    return Err("A shard needs --checkpoint, it's what merge reads to put the shards back together".into());
+                }
+                range = range.shard(k, n)?;
+            }
+            let threads = args.number("threads", default_threads() as u64)? as usize;
+            let filter = Expression::parse_filter(args.flag("filter").unwrap_or(""))?;
+            let records = match args.flag("records") {
+                Some(format) => Some(RecordWriter::open(RecordFormat::parse(format)?, args.flag("output"))?),
+                None => None,
+            };
+            let save_dir = args.flag("save").map(|_| output_dir.clone());
+            let top = match args.flag("top") {
+                Some(_) => match args.number("top", 0)? as usize {
+                    0 => return Err("--top should be at least 1".into()),
+                    size => Some((Metric::parse(args.flag("metric").unwrap_or("total"))?, size)),
+                },
+                None => None,
+            };
+            let database = args.flag("database").map(String::from);
+            let config = SweepConfig { range, difficulty, filter, save_dir, top, database };
+            let checkpoint = args.flag("checkpoint");
+            let stats = sweep::run_sweep(&config, threads, records.as_ref(), checkpoint, args.flag("quiet").is_none())?;
+            // Keep stdout clean for the records if they're going there
+            eprintln!("{}", sweep_summary(&config, &stats, checkpoint.unwrap_or_default()));
+            write_leaderboard(&args, &config, &stats.progress.leaderboard)?;
+        }
+        "merge" => {
+            let paths: Vec<String> = args.positional.iter().skip(1).cloned().collect();
+            let merged = merge::merge_checkpoints(&paths, args.flag("output"), args.flag("database"))?;
+            if let Some(path) = args.flag("checkpoint") {
+                merged.save(path)?;
+            }
+            let progress = &merged.progress;
+            let range = &merged.config.range;
+            let mut summary = format!("Merged {} shards covering {} seeds from {:#010x}, {} kept", paths.len(), range.count, range.start, progress.kept);
+            if let Some((seed, length)) = progress.best {
+                summary += &format!(", best route {} ({})", length, VHRandom::from_seed(seed).get_code());
+            }
+            let stats = sweep::SweepStats { seeds: range.count, progress: progress.clone(), seconds: 0.0, finished: true };
+            let breakdown = stats.breakdown(&merged.config);
+            if !breakdown.is_empty() {
+                summary += &format!("\n{}", breakdown);
+            }
+            eprintln!("{}", summary);
+            write_leaderboard(&args, &merged.config, &progress.leaderboard)?;
+        }
+        "db" => {
+            let mut db = ResultsDb::open(args.argument(0, "the database file")?)?;
+            println!("{}", results::summary(&db));
+            for s in args.positional.iter().skip(2) {
+                let seed = results::parse_seed(s).ok_or(format!("'{}' isn't a seed or a code", s))?;
+                match db.get(seed)? {
+                    Some(r) => println!("{}", results::describe(&r)),
+                    None => println!("{:#010x} isn't in this database", seed),
+                }
+            }
+        }
+        "query" => {
+            let mut db = ResultsDb::open(args.argument(0, "the database file")?)?;
+            let terms: Vec<&str> = args.positional.iter().skip(2).map(|t| t.as_str()).collect();
+            let query = Query::parse(&terms.join(" "))?;
+            let limit = args.number("limit", u64::MAX)?;
+            let mut out = std::io::BufWriter::new(std::io::stdout());
+            let (mut written, mut shown, mut cut_off) = (Ok(()), 0, false);
+            let matched = db.scan(&query, |r| {
+                // Only stop once there's a match past the limit, so exactly --limit matches
+                // isn't reported as cut off
+                if shown == limit {
+                    cut_off = true;
+                    return false;
+                }
+                written = writeln!(out, "{}", results::describe(r));
+                shown += 1;
+                written.is_ok()
+            })?;
+            written?;
+            out.flush()?;
+            match cut_off {
+                true => eprintln!("Stopped at the first {} matches", limit),
+                false => eprintln!("{} of {} seeds match", matched, db.count),
+            }
+        }
+        "rank" => {
+            let dir = args.positional.get(1).map_or(output_dir.as_str(), |d| d.as_str());
+            let ranked = map::rank_saved_maps(dir)?;
+            match args.format(&["text", "json"])?.as_str() {
+                "json" => {
+                    let rows = ranked.iter().map(|(length, codes)| {
+                        let codes = codes.iter().map(|c| c.as_str().into()).collect();
+                        Value::Array(vec![(*length as u64).into(), Value::Array(codes)])
+                    }).collect();
+                    println!("{}", Value::Array(rows).to_json()?);
+                }
+                _ => for (length, codes) in ranked {
+                    println!("{}\t{}", length, codes.join(" "));
+                },
+            }
+        }
+        "explain" => {
+            let map = OverworldMap::from_code(&fcargs!(args.argument(0, "the code")?, difficulty))?;
+            match args.format(&["text", "json"])?.as_str() {
+                "json" => {
+                    let mut value = map.to_json_value();
+                    if let Value::Object(o) = &mut value {
+                        o.remove("tiles");
+                    }
+                    println!("{}", value.to_json()?);
+                }
+                _ => print!("{}", explain(&map)),
+            }
+        }
+        _ => return Err(format!("Unknown command '{}'\n\n{}", command, USAGE).into()),
+    }
+    Ok(true)
+}
+
+// The leaderboard goes to --leaderboard if it's given, otherwise stdout (after any records)
+fn write_leaderboard(args: &Args, config: &SweepConfig, board: &Leaderboard) -> Result<bool, Box<dyn Error>> {
+    let Some((metric, _)) = config.top else { return Ok(false) };
+    let text = match args.format(&["text", "json"])?.as_str() {
+        "json" => {
+            let mut o = std::collections::BTreeMap::new();
+            o.insert("metric".to_string(), metric.name().into());
+            o.insert("entries".to_string(), board.to_json_value());
+            Value::Object(o).to_json()? + "\n"
+        }
+        _ => board.to_text(metric),
+    };
+    match args.flag("leaderboard") {
+        Some(path) => std::fs::write(path, text)?,
+        None => std::io::stdout().write_all(text.as_bytes())?,
+    }
+    Ok(true)
+}
+
+fn sweep_summary(config: &SweepConfig, stats: &sweep::SweepStats, checkpoint: &str) -> String {
+    let mut summary = stats.summary(config);
+    if !stats.finished {
+        summary += &format!(", carry on with --resume {}", checkpoint);
+    }
+    let breakdown = stats.breakdown(config);
+    if !breakdown.is_empty() {
+        summary += &format!("\n{}", breakdown);
+    }
+    summary
+}
+
+/// A plain description of how a generated map came out: its seed, base map, where everything
+/// is and the best route through it
+pub fn explain(map: &OverworldMap) -> String {
+    let info = map.info();
+    let mut out = format!("{} (seed {:#010x}) on {}\n", info.code, info.seed, map::difficulty_text(&info.difficulty));
+    out += &format!("Base map {}, rotation {}\n", info.base_map, info.base_rotation);
+    for (c, (x, y)) in map.feature_positions() {
+        out += &format!("{} {:<14}{:>2},{:>2}\n", c, map::symbol_name(c), x, y);
+    }
+    let route = map.shortest_route();
+    let stops: Vec<String> = route.waypoints.iter().map(|(c, (x, y))| format!("{} ({},{})", c, x, y)).collect();
+    out += &format!("Route: {}\n", stops.join(" -> "));
+    out += &format!("First half {}, last half {}, total {}\n", route.first_half, route.last_half, route.first_half + route.last_half);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_arguments() {
+        let args = Args::parse(&strings(&["sweep", "--start", "5", "--save", "--filter=map4,last3", "extra"])).unwrap();
+        assert!(args.positional == strings(&["sweep", "extra"]));
+        assert!(args.number("start", 0).unwrap() == 5 && args.number("count", 7).unwrap() == 7);
+        assert!(args.flag("save") == Some("") && args.flag("filter") == Some("map4,last3"));
+        let filter = Args::parse(&strings(&["sweep", "--filter", "base == 4 && dist(@, $) < 8"])).unwrap();
+        assert!(Expression::parse_filter(filter.flag("filter").unwrap()).unwrap().is_some());
+        assert!(parse_shard("3/16").unwrap() == (3, 16) && parse_shard("3").is_err() && parse_shard("a/2").is_err());
+        assert!(Args::parse(&strings(&["gen", "--difficulty"])).is_err());
+        assert!(parse_difficulty("pro").unwrap() == Difficulty::PRO && parse_difficulty("Extreme").is_err());
+        assert!(run(&strings(&["frobnicate"])).is_err());
+        assert!(run(&strings(&["sweep", "--start", "4294967296", "--count", "2"])).is_err());
+        assert!(run(&strings(&["sweep", "--count", "4294967297"])).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_flags() {
+        let check = |args: &[&str]| Args::parse(&strings(args)).unwrap().check_flags(args[0]);
+        assert!(check(&["sweep", "--checkpoint", "a.json", "--quiet", "--difficulty", "Hard"]).is_ok());
+        assert!(check(&["gen", "ABCDEFGHIJ", "--format", "json", "--genmaps", "out"]).is_ok());
+        let error = check(&["sweep", "--checkpiont", "a.json"]).unwrap_err().to_string();
+        assert!(error.starts_with("sweep doesn't take --checkpiont") && error.contains("Usage:"));
+        assert!(check(&["gen", "ABCDEFGHIJ", "--top", "5"]).is_err());
+        assert!(check(&["db", "a.db", "--limit", "5"]).is_err());
+        assert!(run(&strings(&["help", "--verbose"])).is_err());
+        let error = run(&strings(&["sweep", "--count", "1", "--top", "0"])).unwrap_err().to_string();
+        assert!(error.contains("--top should be at least 1"));
+    }
+}
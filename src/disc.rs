@@ -0,0 +1,280 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Where the base maps live on the Virtual Hydlide disc
+pub fn base_map_path(n: u32) -> String {
+    format!("HYDLIDE/MAP01/GR_BASE{}.BIN", n)
+}
+
+const SECTOR_SIZE: u64 = 2048;
+const RAW_SECTOR_SIZE: u64 = 2352;
+const SYNC: [u8; 12] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum SectorLayout {
+    // Plain .iso, just the 2048 bytes of user data per sector
+    Cooked,
+    // Raw 2352 byte sectors as in a .bin: sync, header, data and error correction. The mode byte
+    // in each header says whether the data starts at 16 (Mode 1) or 24 (Mode 2 form 1).
+    Raw,
+}
+
+/// Reads files out of the ISO9660 filesystem on a disc image, either a plain .iso or a raw
+/// Mode 1/2352 (or Mode 2/2352) .bin, optionally by way of its .cue
+pub struct DiscImage<R: Read + Seek> {
+    reader: R,
+    layout: SectorLayout,
+}
+
+impl DiscImage<File> {
+    /// Opens an image file. For a .cue the data track's .bin is opened instead.
+    pub fn open(path: &str) -> Result<DiscImage<File>, Box<dyn Error>> {
+        let path = Path::new(path);
+        let is_cue = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("cue"));
+        let image = if is_cue { cue_data_file(path)? } else { path.to_path_buf() };
+        DiscImage::new(File::open(image)?)
+    }
+}
+
+// The data track's FILE in a cue sheet, relative to the cue sheet itself
+fn cue_data_file(cue: &Path) -> Result<std::path::PathBuf, Box<dyn Error>> {
+    let name = cue_data_file_name(&std::fs::read_to_string(cue)?)?;
+    Ok(cue.parent().unwrap_or(Path::new(".")).join(name))
+}
+
+// The first FILE whose first TRACK isn't audio. On Saturn discs that's normally the first FILE,
+// but rips that put the audio first would otherwise hand back a track with no filesystem.
+fn cue_data_file_name(sheet: &str) -> Result<String, Box<dyn Error>> {
+    let mut file: Option<&str> = None;
+    let mut seen_audio = false;
+    for line in sheet.lines() {
+        let line = line.trim();
+        if line.get(..5).is_some_and(|p| p.eq_ignore_ascii_case("FILE ")) {
+            let rest = &line[5..];
+            file = Some(match rest.strip_prefix('"') {
+                Some(quoted) => quoted.split('"').next().unwrap_or(""),
+                None => rest.split_whitespace().next().unwrap_or(""),
+            });
+        } else if line.to_ascii_uppercase().starts_with("TRACK ") {
+            // Only the first TRACK after a FILE says what the file starts with
+            let Some(name) = file.take() else { continue };
+            if line.to_ascii_uppercase().contains("AUDIO") {
+                seen_audio = true;
+            } else {
+                return Ok(name.to_string());
+            }
+        }
+    }
+    Err(if seen_audio { "The cue sheet only has audio tracks" } else { "No data track in the cue sheet" }.into())
+}
+
+impl<R: Read + Seek> DiscImage<R> {
+    pub fn new(mut reader: R) -> Result<DiscImage<R>, Box<dyn Error>> {
+        let mut start = [0u8; 12];
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut start)?;
+        let layout = if start == SYNC { SectorLayout::Raw } else { SectorLayout::Cooked };
+        let mut image = DiscImage { reader, layout };
+
+        // The primary volume descriptor is always sector 16
+        let pvd = image.read_sector(16)?;
+        if pvd[0] != 1 || &pvd[1..6] != b"CD001" {
+            return Err("Not an ISO9660 disc image".into());
+        }
+        Ok(image)
+    }
+
+    /// The 2048 bytes of user data in a sector
+    pub fn read_sector(&mut self, lba: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+        let offset = match self.layout {
+            SectorLayout::Cooked => lba as u64 * SECTOR_SIZE,
+            SectorLayout::Raw => {
+                let mut header = [0u8; 16];
+                self.reader.seek(SeekFrom::Start(lba as u64 * RAW_SECTOR_SIZE))?;
+                self.reader.read_exact(&mut header)?;
+                match header[15] {
+                    2 => lba as u64 * RAW_SECTOR_SIZE + 24,
+                    _ => lba as u64 * RAW_SECTOR_SIZE + 16,
+                }
+            }
+        };
+        let mut data = vec![0u8; SECTOR_SIZE as usize];
+        self.reader.seek(SeekFrom::Start(offset))?;
+        self.reader.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    fn read_extent(&mut self, lba: u32, length: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut data = Vec::with_capacity(length as usize);
+        let sectors = (length as u64).div_ceil(SECTOR_SIZE) as u32;
+        for i in 0..sectors {
+            data.extend(self.read_sector(lba + i)?);
+        }
+        data.truncate(length as usize);
+        Ok(data)
+    }
+
+    /// Reads a file by its path on the disc, like "HYDLIDE/MAP01/GR_BASE1.BIN". Matching is
+    /// case insensitive and ignores the ";1" version suffix.
+    pub fn read_file(&mut self, path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        let pvd = self.read_sector(16)?;
+        // The root directory's record is embedded in the volume descriptor
+        let mut entry = DirEntry::parse(&pvd[156..190]).ok_or("Bad root directory record")?;
+        for part in path.split('/').filter(|p| !p.is_empty()) {
+            if !entry.is_dir {
+                return Err(format!("{} is not a directory on the disc", entry.name).into());
+            }
+            let dir = self.read_extent(entry.lba, entry.length)?;
+            entry = list_directory(&dir).into_iter()
+                .find(|e| e.name.eq_ignore_ascii_case(part))
+                .ok_or(format!("{} is not on the disc", path))?;
+        }
+        if entry.is_dir {
+            return Err(format!("{} is a directory", path).into());
+        }
+        self.read_extent(entry.lba, entry.length)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct DirEntry {
+    name: String,
+    lba: u32,
+    length: u32,
+    is_dir: bool,
+}
+
+impl DirEntry {
+    fn parse(record: &[u8]) -> Option<DirEntry> {
+        let record_length = *record.first()? as usize;
+        let name_length = *record.get(32)? as usize;
+        if record_length < 33 + name_length || record.len() < record_length {
+            return None;
+        }
+        let raw_name = &record[33..33 + name_length];
+        let name = String::from_utf8_lossy(raw_name);
+        let name = name.split(';').next().unwrap_or("").trim_end_matches('.').to_string();
+        Some(DirEntry {
+            name,
+            lba: u32::from_le_bytes(record[2..6].try_into().ok()?),
+            length: u32::from_le_bytes(record[10..14].try_into().ok()?),
+            is_dir: record[25] & 2 != 0,
+        })
+    }
+}
+
+// Records never cross a sector boundary, a zero length byte means skip to the next sector
+fn list_directory(dir: &[u8]) -> Vec<DirEntry> {
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    while pos < dir.len() {
+        let record_length = dir[pos] as usize;
+        if record_length == 0 {
+            pos = (pos / SECTOR_SIZE as usize + 1) * SECTOR_SIZE as usize;
+            continue;
+        }
+        if let Some(entry) = DirEntry::parse(&dir[pos..]) {
+            entries.push(entry);
+        }
+        pos += record_length;
+    }
+    entries
+}
+
+/// Copies the five base maps off a disc image into `dir`, named the way load_base_map expects
+pub fn extract_base_maps(image: &str, dir: &str) -> Result<bool, Box<dyn Error>> {
+    let mut disc = DiscImage::open(image)?;
+    std::fs::create_dir_all(dir)?;
+    for n in 1..6 {
+        let data = disc.read_file(&base_map_path(n))?;
+        std::fs::write(Path::new(dir).join(format!("GR_BASE{}.BIN", n)), data)?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn record(name: &[u8], lba: u32, length: u32, is_dir: bool) -> Vec<u8> {
+        let mut r = vec![0u8; 33];
+        r[2..6].copy_from_slice(&lba.to_le_bytes());
+        r[10..14].copy_from_slice(&length.to_le_bytes());
+        r[25] = if is_dir { 2 } else { 0 };
+        r[32] = name.len() as u8;
+        r.extend_from_slice(name);
+        if r.len() % 2 == 1 { r.push(0); }
+        r[0] = r.len() as u8;
+        r
+    }
+
+    /// A tiny ISO with HYDLIDE/MAP01/ holding the given files, for tests elsewhere too
+    pub(crate) fn build_iso(files: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        // 16 empty, PVD at 16, root at 18, HYDLIDE at 19, MAP01 at 20, files after
+        let mut sectors: Vec<Vec<u8>> = vec![vec![0u8; 2048]; 21];
+        let mut map01 = Vec::new();
+        let mut next = 21u32;
+        for (name, data) in files.iter() {
+            map01.extend(record(format!("{};1", name).as_bytes(), next, data.len() as u32, false));
+            for chunk in data.chunks(2048) {
+                let mut s = chunk.to_vec();
+                s.resize(2048, 0);
+                sectors.push(s);
+                next += 1;
+            }
+        }
+        let root = [record(&[0], 18, 2048, true), record(&[1], 18, 2048, true), record(b"HYDLIDE", 19, 2048, true)].concat();
+        let hydlide = [record(&[0], 19, 2048, true), record(&[1], 18, 2048, true), record(b"MAP01", 20, 2048, true)].concat();
+        for (sector, contents) in [(18, root), (19, hydlide), (20, map01)] {
+            sectors[sector][..contents.len()].copy_from_slice(&contents);
+        }
+        sectors[16][0] = 1;
+        sectors[16][1..6].copy_from_slice(b"CD001");
+        let root_record = record(&[0], 18, 2048, true);
+        sectors[16][156..156 + root_record.len()].copy_from_slice(&root_record);
+        sectors.concat()
+    }
+
+    // Wraps each 2048 byte sector in a raw 2352 byte one, junk where the ECC would be
+    fn to_raw(iso: &[u8], mode: u8) -> Vec<u8> {
+        let mut raw = Vec::new();
+        for sector in iso.chunks(2048) {
+            raw.extend_from_slice(&SYNC);
+            raw.extend_from_slice(&[0, 2, 0, mode]);
+            if mode == 2 { raw.extend_from_slice(&[0; 8]); }
+            raw.extend_from_slice(sector);
+            raw.resize(raw.len().div_ceil(2352) * 2352, 0xaa);
+        }
+        raw
+    }
+
+    #[test]
+    fn reads_files_from_all_layouts() {
+        let big: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        let iso = build_iso(&[("GR_BASE1.BIN", vec![1, 2, 3]), ("GR_BASE2.BIN", big.clone())]);
+        for image in [iso.clone(), to_raw(&iso, 1), to_raw(&iso, 2)] {
+            let mut disc = DiscImage::new(Cursor::new(image)).unwrap();
+            assert!(disc.read_file(&base_map_path(1)).unwrap() == vec![1, 2, 3]);
+            assert!(disc.read_file("hydlide/map01/gr_base2.bin").unwrap() == big);
+            assert!(disc.read_file(&base_map_path(3)).is_err());
+            assert!(disc.read_file("HYDLIDE").is_err());
+        }
+        assert!(DiscImage::new(Cursor::new(vec![0u8; 40000])).is_err());
+    }
+
+    #[test]
+    fn finds_the_data_track_in_a_cue_sheet() {
+        let saturn = "FILE \"Virtual Hydlide (Track 01).bin\" BINARY\n  TRACK 01 MODE1/2352\n    INDEX 01 00:00:00\nFILE \"Virtual Hydlide (Track 02).bin\" BINARY\n  TRACK 02 AUDIO\n";
+        assert!(cue_data_file_name(saturn).unwrap() == "Virtual Hydlide (Track 01).bin");
+        let audio_first = "REM a rip with the audio first\nFILE track1.bin BINARY\n  TRACK 01 AUDIO\n    INDEX 01 00:00:00\nFILE track2.bin BINARY\n  TRACK 02 MODE2/2352\n    INDEX 01 00:00:00\n";
+        assert!(cue_data_file_name(audio_first).unwrap() == "track2.bin");
+        assert!(cue_data_file_name("FILE a.bin BINARY\n  TRACK 01 AUDIO\n").is_err());
+        assert!(cue_data_file_name("FILE a.bin BINARY\n").is_err());
+        // Byte 5 of the REM line is in the middle of the é
+        let accented = "REM é rippé par quelqu'un\nFILE \"données.bin\" BINARY\n  TRACK 01 MODE1/2352\n";
+        assert!(cue_data_file_name(accented).unwrap() == "données.bin");
+    }
+}
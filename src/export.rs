@@ -0,0 +1,312 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+use crate::json::{self, Value};
+use crate::map::{self, MapInfo, OverworldMap, Tile};
+
+/// Bumped whenever the JSON layout changes in a way older readers can't handle
+pub const JSON_FORMAT_VERSION: u64 = 1;
+
+// The features written out by name in the JSON, by legend letter. The elevators come in a pair
+// and are written separately.
+const JSON_FEATURES: &str = "RMFTGVS$C";
+
+fn position(p: (usize, usize)) -> Value {
+    Value::Array(vec![p.0.into(), p.1.into()])
+}
+
+impl OverworldMap {
+    /// A self-contained JSON description of the map, meant for the spreadsheet scripts and the
+    /// webpage so they don't have to know the BIN layout. Tiles are [id, rotation, height]
+    /// triples in row order. Features that aren't on the map (and the route, if any of its
+    /// stops are missing) are null. `e` is an array of both elevators in row order, like the
+    /// sweep records have it.
+    pub fn to_json_value(&self) -> Value {
+        let info = self.info();
+        let mut o = BTreeMap::new();
+        o.insert("format".to_string(), JSON_FORMAT_VERSION.into());
+        o.insert("code".to_string(), info.code.as_str().into());
+        o.insert("seed".to_string(), (info.seed as u64).into());
+        o.insert("difficulty".to_string(), map::difficulty_text(&info.difficulty).into());
+        o.insert("base_map".to_string(), (info.base_map as u64).into());
+        o.insert("base_rotation".to_string(), (info.base_rotation as u64).into());
+        o.insert("retries".to_string(), (info.retries as u64).into());
+        o.insert("width".to_string(), self.width().into());
+        o.insert("height".to_string(), self.height().into());
+        o.insert("start".to_string(), self.find_tile(0xff).map_or(Value::Null, position));
+
+        let mut features = BTreeMap::new();
+        for c in JSON_FEATURES.chars() {
+            let at = self.find_tile(map::symbol_tile_id(c).unwrap());
+            features.insert(c.to_string(), at.map_or(Value::Null, position));
+        }
+        let elevators = self.feature_positions().into_iter().filter(|&(c, _)| c == 'e').map(|(_, p)| position(p)).collect();
+        features.insert("e".to_string(), Value::Array(elevators));
+        o.insert("features".to_string(), Value::Object(features));
+
+        let complete = "@eFRVSC".chars().all(|c| self.find_tile(map::symbol_tile_id(c).unwrap()).is_some());
+        let route = if complete {
+            let route = self.shortest_route();
+            let mut r = BTreeMap::new();
+            r.insert("first_half".to_string(), (route.first_half as u64).into());
+            r.insert("last_half".to_string(), (route.last_half as u64).into());
+            r.insert("total".to_string(), ((route.first_half + route.last_half) as u64).into());
+            r.insert("waypoints".to_string(), Value::Array(route.waypoints.iter()
+                .map(|&(c, (x, y))| Value::Array(vec![c.to_string().as_str().into(), x.into(), y.into()]))
+                .collect()));
+            Value::Object(r)
+        } else {
+            Value::Null
+        };
+        o.insert("route".to_string(), route);
+
+        o.insert("tiles".to_string(), Value::Array(self.tiles().iter()
+            .map(|t| Value::Array(vec![(t.id as u64).into(), (t.rotation as i64).into(), (t.height as i64).into()]))
+            .collect()));
+        Value::Object(o)
+    }
+
+    pub fn to_json(&self) -> Result<String, Box<dyn Error>> {
+        self.to_json_value().to_json()
+    }
+
+    /// Reads a map written by to_json. Only the generation info and the tiles are read back,
+    /// everything else in the file is derived from those.
+    pub fn from_json(text: &str) -> Result<OverworldMap, Box<dyn Error>> {
+        let v = json::parse(text)?;
+        let format = v.get("format").and_then(Value::as_u64).ok_or("Map JSON has no format version")?;
+        if format > JSON_FORMAT_VERSION {
+            return Err(format!("Map JSON format {} is newer than this program understands", format).into());
+        }
+        let field = |name: &str| v.get(name).ok_or(format!("Map JSON is missing '{}'", name));
+        let number = |name: &str| -> Result<u64, Box<dyn Error>> {
+            Ok(field(name)?.as_u64().ok_or(format!("Map JSON '{}' is not a number", name))?)
+        };
+
+        // Every map the game makes is 50x50, and checking first means a made up size can't
+        // overflow or ask for a huge allocation
+        let (width, height) = (number("width")?, number("height")?);
+        if (width, height) != (50, 50) {
+            return Err(format!("Map JSON is {}x{}, maps are 50x50", width, height).into());
+        }
+        let (width, height) = (width as usize, height as usize);
+        let tile_error = "Each tile should be [id, rotation, height], with an id of 0-255 and the others -128-127";
+        let mut tiles = Vec::with_capacity(width * height);
+        for t in field("tiles")?.as_array().ok_or("Map JSON 'tiles' is not an array")? {
+            let t = t.as_array().filter(|t| t.len() == 3).ok_or(tile_error)?;
+            let (Some(id), Some(rotation), Some(height)) = (t[0].as_u64(), t[1].as_i64(), t[2].as_i64())
+                else { return Err(tile_error.into()) };
+            let (Ok(id), Ok(rotation), Ok(height)) = (u8::try_from(id), i8::try_from(rotation), i8::try_from(height))
+                else { return Err(tile_error.into()) };
+            tiles.push(Tile { id, rotation, height });
+        }
+        let mut map = OverworldMap::from_tiles(width, height, tiles)?;
+        let out_of_range = |name: &str| format!("Map JSON '{}' is out of range", name);
+        map.set_info(MapInfo {
+            code: field("code")?.as_str().ok_or("Map JSON 'code' is not a string")?.to_string(),
+            seed: u32::try_from(number("seed")?).map_err(|_| out_of_range("seed"))?,
+            difficulty: map::text_difficulty(field("difficulty")?.as_str().unwrap_or("Easy")),
+            base_map: u32::try_from(number("base_map")?).map_err(|_| out_of_range("base_map"))?,
+            base_rotation: u8::try_from(number("base_rotation")?).map_err(|_| out_of_range("base_rotation"))?,
+            retries: u32::try_from(number("retries")?).map_err(|_| out_of_range("retries"))?,
+        });
+        Ok(map)
+    }
+
+    pub fn save_map_json(&self, dir: &str, s: &str) -> Result<bool, Box<dyn Error>> {
+        std::fs::write(Path::new(dir).join(format!("{}.json", s)), self.to_json()?)?;
+        Ok(true)
+    }
+}
+
+// Tiled keeps rotations in the top bits of each tile's gid as flips. A game rotation of 1 is a
+// quarter turn clockwise, which Tiled writes as a diagonal flip plus a horizontal flip.
+const TMX_FLIP_HORIZONTAL: u32 = 0x80000000;
+const TMX_FLIP_VERTICAL: u32 = 0x40000000;
+const TMX_FLIP_DIAGONAL: u32 = 0x20000000;
+const TMX_TILE_SIZE: usize = 16;
+
+fn tmx_rotation_flags(rotation: i8) -> u32 {
+    match rotation.rem_euclid(4) {
+        1 => TMX_FLIP_DIAGONAL | TMX_FLIP_HORIZONTAL,
+        2 => TMX_FLIP_HORIZONTAL | TMX_FLIP_VERTICAL,
+        3 => TMX_FLIP_DIAGONAL | TMX_FLIP_VERTICAL,
+        _ => 0,
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+impl OverworldMap {
+    /// Writes the map as a Tiled .tmx file. The tile layer uses the tile id plus one as the gid
+    /// (gid 0 is "no tile" in Tiled) and the rotation as flip flags, against a 16x16 tileset
+    /// image `vh_tiles.png` laid out 16 tiles wide in id order, which has to be supplied
+    /// separately. The start and features, both elevators included, go on an object layer
+    /// named by legend letter.
+    pub fn to_tmx(&self) -> String {
+        let info = self.info();
+        let features = self.feature_positions();
+
+        let mut out = String::new();
+        out += "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n";
+        out += &format!("<map version=\"1.10\" orientation=\"orthogonal\" renderorder=\"right-down\" width=\"{}\" height=\"{}\" tilewidth=\"{}\" tileheight=\"{}\" infinite=\"0\" nextlayerid=\"3\" nextobjectid=\"{}\">\n",
+            self.width(), self.height(), TMX_TILE_SIZE, TMX_TILE_SIZE, features.len() + 1);
+        out += " <properties>\n";
+        out += &format!("  <property name=\"code\" value=\"{}\"/>\n", xml_escape(&info.code));
+        out += &format!("  <property name=\"seed\" value=\"{}\"/>\n", info.seed);
+        out += &format!("  <property name=\"difficulty\" value=\"{}\"/>\n", map::difficulty_text(&info.difficulty));
+        out += &format!("  <property name=\"base_map\" type=\"int\" value=\"{}\"/>\n", info.base_map);
+        out += &format!("  <property name=\"base_rotation\" type=\"int\" value=\"{}\"/>\n", info.base_rotation);
+        out += " </properties>\n";
+        out += &format!(" <tileset firstgid=\"1\" name=\"vh_tiles\" tilewidth=\"{0}\" tileheight=\"{0}\" tilecount=\"256\" columns=\"16\">\n", TMX_TILE_SIZE);
+        out += &format!("  <image source=\"vh_tiles.png\" width=\"{0}\" height=\"{0}\"/>\n", TMX_TILE_SIZE * 16);
+        out += " </tileset>\n";
+        out += &format!(" <layer id=\"1\" name=\"Tiles\" width=\"{}\" height=\"{}\">\n", self.width(), self.height());
+        out += "  <data encoding=\"csv\">\n";
+        let rows: Vec<String> = self.tiles().chunks(self.width())
+            .map(|row| row.iter()
+                .map(|t| ((t.id as u32 + 1) | tmx_rotation_flags(t.rotation)).to_string())
+                .collect::<Vec<_>>()
+                .join(","))
+            .collect();
+        out += &rows.join(",\n");
+        out += "\n  </data>\n";
+        out += " </layer>\n";
+        out += " <objectgroup id=\"2\" name=\"Features\">\n";
+        for (i, (c, (x, y))) in features.iter().enumerate() {
+            out += &format!("  <object id=\"{}\" name=\"{}\" type=\"{}\" x=\"{}\" y=\"{}\" width=\"{3}\" height=\"{3}\"/>\n",
+                i + 1, map::symbol_name(*c), xml_escape(&c.to_string()), x * TMX_TILE_SIZE, y * TMX_TILE_SIZE);
+        }
+        out += " </objectgroup>\n";
+        out += "</map>\n";
+        out
+    }
+
+    pub fn save_map_tmx(&self, dir: &str, s: &str) -> Result<bool, Box<dyn Error>> {
+        std::fs::write(Path::new(dir).join(format!("{}.tmx", s)), self.to_tmx())?;
+        Ok(true)
+    }
+}
+
+impl OverworldMap {
+    /// The map in the layout load_mednafen_map reads: the game's 3 bytes a tile (rotation, id,
+    /// height) as the Saturn has them in RAM, byte-swapped in 16 bit words the way Mednafen
+    /// saves memory. The game doesn't mark the start tile, so it's written as the default tile
+    /// it sits on, and the player's start comes from the game itself.
+    pub fn to_mednafen_bytes(&self) -> Vec<u8> {
+        let mut raw = Vec::with_capacity(self.tiles().len() * 3);
+        for pair in self.tiles().chunks(2) {
+            let id = |t: &Tile| if t.id == 0xff { 1 } else { t.id };
+            let (t1, t2) = (&pair[0], pair.get(1).unwrap_or(&pair[0]));
+            raw.extend_from_slice(&[t1.rotation as u8, id(t1), id(t2), t1.height as u8, t2.height as u8, t2.rotation as u8]);
+        }
+        raw.truncate(self.tiles().len() * 3);
+        raw
+    }
+
+    pub fn save_mednafen_map(&self, path: &str) -> Result<bool, Box<dyn Error>> {
+        std::fs::write(path, self.to_mednafen_bytes())?;
+        Ok(true)
+    }
+}
+
+pub fn load_map_json(dir: &str, s: &str) -> Result<OverworldMap, Box<dyn Error>> {
+    let text = std::fs::read_to_string(Path::new(dir).join(format!("{}.json", s)))?;
+    OverworldMap::from_json(&text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_round_trip() {
+        let mut tiles = vec![Tile { id: 1, rotation: 0, height: 0 }; 50*50];
+        for &(c, x, y) in [('@', 0, 0), ('e', 2, 0), ('F', 4, 0), ('R', 6, 0), ('V', 8, 0), ('S', 8, 2), ('C', 8, 5), ('e', 1, 0)].iter() {
+            tiles[x + y * 50].id = map::symbol_tile_id(c).unwrap();
+        }
+        tiles[7].rotation = 3;
+        tiles[9].height = -2;
+        let mut m = OverworldMap::from_tiles(50, 50, tiles).unwrap();
+        m.set_info(MapInfo { code: "♂BBBBBBBB♀".to_string(), seed: 0xfffc0000, difficulty: map::Difficulty::Hard, base_map: 4, base_rotation: 2, retries: 3 });
+
+        let text = m.to_json().unwrap();
+        let v = json::parse(&text).unwrap();
+        assert!(v.get("start") == Some(&position((0, 0))));
+        assert!(v.get("features").unwrap().get("$") == Some(&Value::Null));
+        assert!(v.get("features").unwrap().get("e") == Some(&Value::Array(vec![position((1, 0)), position((2, 0))])));
+        assert!(v.get("route").unwrap().get("total").unwrap().as_u64() == Some(13));
+
+        let loaded = OverworldMap::from_json(&text).unwrap();
+        assert!(loaded == m);
+
+        let without_retries = text.replace(",\"retries\":3", "");
+        assert!(without_retries != text && OverworldMap::from_json(&without_retries).is_err());
+    }
+
+    #[test]
+    fn tmx_layers() {
+        let mut tiles = vec![Tile { id: 1, rotation: 0, height: 0 }; 50*50];
+        tiles[0] = Tile { id: 0xe, rotation: 1, height: 0 };
+        tiles[1] = Tile { id: 0xff, rotation: 2, height: 0 };
+        tiles[60].id = map::symbol_tile_id('e').unwrap();
+        tiles[200].id = map::symbol_tile_id('e').unwrap();
+        let mut m = OverworldMap::from_tiles(50, 50, tiles).unwrap();
+        m.set_info(MapInfo { code: "B&BBBBBBBB".to_string(), ..MapInfo::default() });
+
+        let tmx = m.to_tmx();
+        assert!(tmx.contains("value=\"B&amp;BBBBBBBB\""));
+        let first_row = tmx.lines().find(|l| l.starts_with(&(15 | TMX_FLIP_DIAGONAL | TMX_FLIP_HORIZONTAL).to_string())).unwrap();
+        assert!(first_row.starts_with(&format!("{},{},2,", 15 | TMX_FLIP_DIAGONAL | TMX_FLIP_HORIZONTAL, 0x100 | TMX_FLIP_HORIZONTAL | TMX_FLIP_VERTICAL)));
+        assert!(first_row.split(',').filter(|s| !s.is_empty()).count() == 50);
+        assert!(tmx.contains("name=\"Start\" type=\"@\" x=\"16\" y=\"0\""));
+        assert!(tmx.matches("type=\"e\"").count() == 2);
+    }
+
+    #[test]
+    fn mednafen_round_trip() {
+        let dumps: [&[u8]; 4] = [
+            include_bytes!("../tests/FNMCNTLGHF.bin"),
+            include_bytes!("../tests/GBBBTSMMBB.bin"),
+            include_bytes!("../tests/BBBBNDTLBB.bin"),
+            include_bytes!("../tests/QBBDGRNQBB.bin"),
+        ];
+        for dump in dumps.iter() {
+            let m = map::load_mednafen_map(dump).unwrap();
+            assert!(m.to_mednafen_bytes() == dump.to_vec());
+            assert!(map::load_mednafen_map(&m.to_mednafen_bytes()).unwrap() == m);
+        }
+    }
+
+    #[test]
+    fn mednafen_start_is_a_default_tile() {
+        let mut tiles = vec![Tile { id: 9, rotation: 1, height: 3 }; 50*50];
+        tiles[1] = Tile { id: 0xff, rotation: 2, height: -1 };
+        let m = OverworldMap::from_tiles(50, 50, tiles).unwrap();
+        let loaded = map::load_mednafen_map(&m.to_mednafen_bytes()).unwrap();
+        assert!(loaded.tiles()[1] == Tile { id: 1, rotation: 2, height: -1 });
+        assert!(loaded.tiles()[2] == Tile { id: 9, rotation: 1, height: 3 });
+    }
+
+    #[test]
+    fn rejects_wrong_tile_count() {
+        let text = r#"{"format":1,"code":"","seed":0,"difficulty":"Easy","base_map":0,"base_rotation":0,"width":50,"height":50,"tiles":[[1,0,0]]}"#;
+        assert!(OverworldMap::from_json(text).is_err());
+    }
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        let mut m = OverworldMap::from_tiles(50, 50, vec![Tile { id: 1, rotation: 0, height: 0 }; 50*50]).unwrap();
+        m.set_info(MapInfo { code: "BBBBBBBBBB".to_string(), seed: 7, ..MapInfo::default() });
+        let text = m.to_json().unwrap();
+        assert!(OverworldMap::from_json(&text).is_ok());
+        for (from, to) in [("\"width\":50", "\"width\":4294967296"), ("\"height\":50", "\"height\":1000000000000"),
+            ("\"seed\":7", "\"seed\":4294967296"), ("\"base_rotation\":0", "\"base_rotation\":256"), ("[1,0,0]", "[256,0,0]"),
+            ("[1,0,0]", "[1,128,0]"), ("[1,0,0]", "[1,0,-129]")] {
+            let changed = text.replacen(from, to, 1);
+            assert!(changed != text && OverworldMap::from_json(&changed).is_err(), "{}", to);
+        }
+    }
+}
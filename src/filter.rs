@@ -0,0 +1,465 @@
+use std::cell::OnceCell;
+use std::error::Error;
+use std::fmt;
+use crate::index::{self, axis_distance};
+use crate::map::{self, Difficulty, OverworldMap, Stage};
+
+/// What's known about a map at one stage of generating it. The base map and rotation are rolled
+/// first, the features turn up one at a time after that, and the route is only there at the end.
+#[derive(Debug)]
+pub struct Facts<'a> {
+    pub stage: Stage,
+    pub difficulty: Difficulty,
+    pub base_map: u32,
+    pub rotation: u8,
+    /// First and last half of the shortest route, None before Stage::Route
+    pub route: Option<(u16, u16)>,
+    // The map as far as it's got, only scanned for features if a filter asks
+    map: Option<&'a OverworldMap>,
+    positions: OnceCell<Vec<(char, (usize, usize))>>,
+}
+
+impl<'a> Facts<'a> {
+    pub fn new(stage: Stage, difficulty: Difficulty, base_map: u32, rotation: u8, map: &'a OverworldMap, route: Option<(u16, u16)>) -> Facts<'a> {
+        Facts { stage, difficulty, base_map, rotation, route, map: Some(map), positions: OnceCell::new() }
+    }
+
+    /// Legend letter and position of every feature on the map so far
+    pub fn positions(&self) -> &[(char, (usize, usize))] {
+        self.positions.get_or_init(|| self.map.map_or(Vec::new(), OverworldMap::feature_positions))
+    }
+}
+
+/// Something that decides which generated maps are worth keeping. A filter is made of parts
+/// that all have to hold, and says which part ruled a map out so sweeps can count them.
+pub trait Filter: fmt::Debug + Send + Sync {
+    /// The index of the first part that rules the map out, if any. Parts that need facts that
+    /// aren't known yet don't rule anything out.
+    fn rejects(&self, facts: &Facts) -> Option<usize>;
+
+    /// What each part is called, in order
+    fn parts(&self) -> Vec<String>;
+
+    /// The earliest stage of generation each part can be decided at. Generation only asks the
+    /// filter at these stages, and stops there if it says no.
+    fn stages(&self) -> Vec<Stage>;
+}
+
+/// The old winnow methods, which still work by name, comma separated
+pub const PRESETS: [(&str, &str); 3] = [
+    ("map4", "base == 4"),
+    ("last3", "last <= 9"),
+    ("under50", "total < 50"),
+];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Var {
+    Base,
+    Rotation,
+    Difficulty,
+    First,
+    Last,
+    Total,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Node {
+    Number(i64),
+    Var(Var),
+    /// Tiles apart along the longer axis, the short way round the wrapping map
+    Dist(char, char),
+    X(char),
+    Y(char),
+    Compare(Box<Node>, Op, Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+}
+
+// A number worked out from the facts. A feature that isn't on the map (the shop on Hard) is
+// Missing, which no comparison is true for. Unknown is for facts that aren't there yet.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Val {
+    Known(i64),
+    Missing,
+    Unknown,
+}
+
+impl Node {
+    fn value(&self, facts: &Facts) -> Val {
+        let placed = |c: char| facts.stage >= Stage::of_feature(c);
+        let coordinate = |c: char, axis: fn(&(usize, usize)) -> usize| match placed(c) {
+            true => facts.positions().iter().find(|(f, _)| *f == c).map_or(Val::Missing, |(_, at)| Val::Known(axis(at) as i64)),
+            false => Val::Unknown,
+        };
+        let route = |f: fn(u16, u16) -> u16| facts.route.map_or(Val::Unknown, |(first, last)| Val::Known(f(first, last) as i64));
+        match self {
+            Node::Number(n) => Val::Known(*n),
+            Node::Var(Var::Base) => Val::Known(facts.base_map as i64),
+            Node::Var(Var::Rotation) => Val::Known(facts.rotation as i64),
+            Node::Var(Var::Difficulty) => Val::Known(index::difficulty_byte(facts.difficulty) as i64),
+            Node::Var(Var::First) => route(|first, _| first),
+            Node::Var(Var::Last) => route(|_, last| last),
+            Node::Var(Var::Total) => route(|first, last| first + last),
+            Node::X(c) => coordinate(*c, |at| at.0),
+            Node::Y(c) => coordinate(*c, |at| at.1),
+            Node::Dist(a, b) => {
+                if !placed(*a) || !placed(*b) {
+                    return Val::Unknown;
+                }
+                let p = facts.positions();
+                // The elevator is there twice, the nearest pair counts
+                let (from, to) = (p.iter().filter(|(f, _)| f == a), p.iter().filter(|(f, _)| f == b));
+                from.flat_map(|(_, (ax, ay))| to.clone().map(move |(_, (bx, by))| axis_distance(*ax, *bx).max(axis_distance(*ay, *by))))
+                    .min().map_or(Val::Missing, |d| Val::Known(d as i64))
+            }
+            _ => Val::Unknown,
+        }
+    }
+
+    // The stage everything it looks at is known by
+    fn stage(&self) -> Stage {
+        match self {
+            Node::Number(_) | Node::Var(Var::Base | Var::Rotation | Var::Difficulty) => Stage::Base,
+            Node::Var(Var::First | Var::Last | Var::Total) => Stage::Route,
+            Node::X(c) | Node::Y(c) => Stage::of_feature(*c),
+            Node::Dist(a, b) => Stage::of_feature(*a).max(Stage::of_feature(*b)),
+            Node::Compare(a, _, b) | Node::And(a, b) | Node::Or(a, b) => a.stage().max(b.stage()),
+            Node::Not(a) => a.stage(),
+        }
+    }
+
+    // Kleene logic: None when it depends on something not known yet
+    fn holds(&self, facts: &Facts) -> Option<bool> {
+        match self {
+            Node::Compare(a, op, b) => match (a.value(facts), b.value(facts)) {
+                (Val::Unknown, _) | (_, Val::Unknown) => None,
+                (Val::Known(a), Val::Known(b)) => Some(match op {
+                    Op::Eq => a == b,
+                    Op::Ne => a != b,
+                    Op::Lt => a < b,
+                    Op::Le => a <= b,
+                    Op::Gt => a > b,
+                    Op::Ge => a >= b,
+                }),
+                _ => Some(false),
+            },
+            Node::And(a, b) => match (a.holds(facts), b.holds(facts)) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            },
+            Node::Or(a, b) => match (a.holds(facts), b.holds(facts)) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            },
+            Node::Not(a) => a.holds(facts).map(|h| !h),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Brackets only where they're needed to read back the same
+        let grouped = |node: &Node, f: &mut fmt::Formatter, around: fn(&Node) -> bool| -> fmt::Result {
+            if around(node) { write!(f, "({})", node) } else { write!(f, "{}", node) }
+        };
+        match self {
+            Node::Number(n) => write!(f, "{}", n),
+            Node::Var(v) => write!(f, "{}", match v {
+                Var::Base => "base",
+                Var::Rotation => "rotation",
+                Var::Difficulty => "difficulty",
+                Var::First => "first",
+                Var::Last => "last",
+                Var::Total => "total",
+            }),
+            Node::Dist(a, b) => write!(f, "dist({}, {})", a, b),
+            Node::X(c) => write!(f, "x({})", c),
+            Node::Y(c) => write!(f, "y({})", c),
+            Node::Compare(a, op, b) => write!(f, "{} {} {}", a, match op {
+                Op::Eq => "==",
+                Op::Ne => "!=",
+                Op::Lt => "<",
+                Op::Le => "<=",
+                Op::Gt => ">",
+                Op::Ge => ">=",
+            }, b),
+            Node::And(a, b) => {
+                grouped(a, f, |n| matches!(n, Node::Or(..)))?;
+                write!(f, " && ")?;
+                grouped(b, f, |n| matches!(n, Node::Or(..) | Node::And(..)))
+            }
+            Node::Or(a, b) => {
+                write!(f, "{} || ", a)?;
+                grouped(b, f, |n| matches!(n, Node::Or(..)))
+            }
+            Node::Not(a) => {
+                write!(f, "!")?;
+                grouped(a, f, |n| !matches!(n, Node::Not(..)))
+            }
+        }
+    }
+}
+
+// Recursive descent over the characters, lowest precedence first: ||, &&, !, comparisons
+struct Parser<'a> {
+    text: &'a str,
+    chars: Vec<char>,
+    at: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, what: &str) -> Box<dyn Error> {
+        let near: String = self.chars[self.at.min(self.chars.len())..].iter().take(12).collect();
+        match near.is_empty() {
+            true => format!("{} at the end of '{}'", what, self.text).into(),
+            false => format!("{} at '{}' in '{}'", what, near, self.text).into(),
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.chars.get(self.at).is_some_and(|c| c.is_whitespace()) {
+            self.at += 1;
+        }
+    }
+
+    fn next_is(&mut self, token: &str) -> bool {
+        self.skip_spaces();
+        token.chars().enumerate().all(|(i, c)| self.chars.get(self.at + i) == Some(&c))
+    }
+
+    // Takes `token` if it's next
+    fn eat(&mut self, token: &str) -> bool {
+        let matches = self.next_is(token);
+        if matches {
+            self.at += token.chars().count();
+        }
+        matches
+    }
+
+    fn or(&mut self) -> Result<Node, Box<dyn Error>> {
+        let mut node = self.and()?;
+        while self.eat("||") {
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> Result<Node, Box<dyn Error>> {
+        let mut node = self.unary()?;
+        while self.eat("&&") {
+            node = Node::And(Box::new(node), Box::new(self.unary()?));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node, Box<dyn Error>> {
+        if !self.next_is("!=") && self.eat("!") {
+            return Ok(Node::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let node = self.or()?;
+            if !self.eat(")") {
+                return Err(self.error("Missing )"));
+            }
+            return Ok(node);
+        }
+        let left = self.value()?;
+        // Longest first so <= isn't read as <
+        let ops = [("==", Op::Eq), ("!=", Op::Ne), ("<=", Op::Le), (">=", Op::Ge), ("<", Op::Lt), (">", Op::Gt)];
+        let Some(&(_, op)) = ops.iter().find(|(token, _)| self.eat(token)) else {
+            return Err(self.error("Expected a comparison (==, !=, <, <=, >, >=)"));
+        };
+        Ok(Node::Compare(Box::new(left), op, Box::new(self.value()?)))
+    }
+
+    // A legend letter as a function argument
+    fn feature(&mut self) -> Result<char, Box<dyn Error>> {
+        self.skip_spaces();
+        let c = *self.chars.get(self.at).ok_or_else(|| self.error("Expected a legend letter"))?;
+        if map::symbol_tile_id(c).is_none() {
+            return Err(self.error(&format!("'{}' isn't a legend letter", c)));
+        }
+        self.at += 1;
+        Ok(c)
+    }
+
+    fn value(&mut self) -> Result<Node, Box<dyn Error>> {
+        self.skip_spaces();
+        let start = self.at;
+        if self.chars.get(self.at) == Some(&'-') {
+            self.at += 1;
+        }
+        while self.chars.get(self.at).is_some_and(|c| c.is_ascii_alphanumeric() || *c == '_') {
+            self.at += 1;
+        }
+        let word: String = self.chars[start..self.at].iter().collect();
+        if let Ok(n) = word.parse::<i64>() {
+            return Ok(Node::Number(n));
+        }
+        let node = match word.as_str() {
+            "base" => Node::Var(Var::Base),
+            "rotation" | "rot" => Node::Var(Var::Rotation),
+            "difficulty" => Node::Var(Var::Difficulty),
+            "first" => Node::Var(Var::First),
+            "last" | "last3" => Node::Var(Var::Last),
+            "total" => Node::Var(Var::Total),
+            "dist" | "x" | "y" => {
+                if !self.eat("(") {
+                    return Err(self.error(&format!("{} needs brackets", word)));
+                }
+                let a = self.feature()?;
+                let node = match word.as_str() {
+                    "dist" => {
+                        if !self.eat(",") {
+                            return Err(self.error("dist takes two legend letters"));
+                        }
+                        Node::Dist(a, self.feature()?)
+                    }
+                    "x" => Node::X(a),
+                    _ => Node::Y(a),
+                };
+                if !self.eat(")") {
+                    return Err(self.error("Missing )"));
+                }
+                node
+            }
+            _ => match ["Easy", "Medium", "Hard", "PRO"].iter().find(|d| d.eq_ignore_ascii_case(&word)) {
+                Some(d) => Node::Number(index::difficulty_byte(map::text_difficulty(d)) as i64),
+                None => {
+                    self.at = start;
+                    return Err(self.error("Expected a number, a name like base or total, or dist(a, b)"));
+                }
+            },
+        };
+        Ok(node)
+    }
+}
+
+/// A filter written as an expression, like `base == 4 && last <= 9 && dist(@, $) < 8`. The
+/// parts are what's joined by the top level &&s.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Expression {
+    parts: Vec<Node>,
+}
+
+impl Expression {
+    pub fn parse(text: &str) -> Result<Expression, Box<dyn Error>> {
+        let mut parser = Parser { text, chars: text.chars().collect(), at: 0 };
+        let node = parser.or()?;
+        parser.skip_spaces();
+        if parser.at < parser.chars.len() {
+            return Err(parser.error("Expected && or ||"));
+        }
+        // The top level &&s are left leaning, so the rightmost part is on top
+        let mut parts = Vec::new();
+        let mut rest = node;
+        while let Node::And(a, b) = rest {
+            parts.push(*b);
+            rest = *a;
+        }
+        parts.push(rest);
+        parts.reverse();
+        Ok(Expression { parts })
+    }
+
+    /// Either the old winnow names, comma separated, or an expression. Empty for no filter.
+    pub fn parse_filter(text: &str) -> Result<Option<Expression>, Box<dyn Error>> {
+        if text.trim().is_empty() {
+            return Ok(None);
+        }
+        let presets: Option<Vec<&str>> = text.split(',')
+            .map(|name| PRESETS.iter().find(|(n, _)| *n == name.trim()).map(|(_, e)| *e))
+            .collect();
+        match presets {
+            Some(expressions) => Expression::parse(&expressions.join(" && ")).map(Some),
+            // Probably a misspelt preset rather than an expression
+            None if text.chars().all(|c| c.is_ascii_alphanumeric() || c == ',' || c == ' ') => {
+                let names: Vec<&str> = PRESETS.iter().map(|(name, _)| *name).collect();
+                Err(format!("Unknown filter '{}', the presets are {}, or write an expression like 'base == 4 && total < 50'", text, names.join(", ")).into())
+            }
+            None => Expression::parse(text).map(Some),
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parts: Vec<String> = self.parts.iter().map(|p| match p {
+            Node::Or(..) => format!("({})", p),
+            _ => p.to_string(),
+        }).collect();
+        write!(f, "{}", parts.join(" && "))
+    }
+}
+
+impl Filter for Expression {
+    fn rejects(&self, facts: &Facts) -> Option<usize> {
+        self.parts.iter().position(|p| p.holds(facts) == Some(false))
+    }
+
+    fn parts(&self) -> Vec<String> {
+        self.parts.iter().map(|p| p.to_string()).collect()
+    }
+
+    fn stages(&self) -> Vec<Stage> {
+        self.parts.iter().map(Node::stage).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn facts(stage: Stage) -> Facts<'static> {
+        let positions = vec![('@', (48, 10)), ('R', (1, 12)), ('V', (12, 30)), ('e', (20, 20)), ('e', (40, 40))];
+        let route = (stage == Stage::Route).then_some((30, 9));
+        Facts { stage, difficulty: Difficulty::Hard, base_map: 4, rotation: 2, route, map: None, positions: positions.into() }
+    }
+
+    #[test]
+    fn parses_and_evaluates() {
+        let yes = ["base == 4 && last3 <= 9 && total < 50", "dist(@, R) == 3", "dist(@,e) == 20", "x(V) == 12 && y(V) >= 30",
+            "difficulty == hard", "rot != 1 || base == 1", "!(base == 1)", "!base == 1", "!(dist(@, $) < 8)", "(first > 40 || last < 10) && total == 39"];
+        let no = ["base == 5", "dist(@, $) < 8", "x($) != 0", "total >= 40 || rotation == 0"];
+        for e in yes {
+            assert!(Expression::parse(e).unwrap().rejects(&facts(Stage::Route)).is_none(), "{}", e);
+        }
+        for e in no {
+            assert!(Expression::parse(e).unwrap().rejects(&facts(Stage::Route)).is_some(), "{}", e);
+        }
+        for e in ["base", "base == ", "dist(@) < 1", "dist(@, X) < 1", "base == 4 &&", "base == 4 extra", "(base == 4", "size > 3"] {
+            assert!(Expression::parse(e).is_err(), "{}", e);
+        }
+    }
+
+    #[test]
+    fn rejects_as_early_as_it_can() {
+        let filter = Expression::parse("total < 50 && base == 4 && dist(@, V) < 8 && x(V) < 10 && (rot == 1 || x(R) > 5)").unwrap();
+        assert!(filter.stages() == vec![Stage::Route, Stage::Base, Stage::Start, Stage::Shop, Stage::Shop]);
+        assert!(filter.rejects(&facts(Stage::Base)).is_none());
+        assert!(filter.rejects(&Facts { base_map: 3, ..facts(Stage::Base) }) == Some(1));
+        assert!(filter.rejects(&facts(Stage::Shop)) == Some(3));
+        assert!(filter.rejects(&facts(Stage::Route)) == Some(2));
+        assert!(filter.parts() == vec!["total < 50", "base == 4", "dist(@, V) < 8", "x(V) < 10", "rotation == 1 || x(R) > 5"]);
+        // Parts print so they parse back the same
+        let grouped = Expression::parse("(base == 1 || base == 4) && !(last > 9 || first > 40)").unwrap();
+        assert!(Expression::parse(&grouped.to_string()).unwrap() == grouped);
+        assert!(grouped.to_string() == "(base == 1 || base == 4) && !(last > 9 || first > 40)");
+        assert!(Expression::parse_filter("map4,last3").unwrap() == Some(Expression::parse("base == 4 && last <= 9").unwrap()));
+        assert!(Expression::parse_filter("").unwrap().is_none() && Expression::parse_filter("map5").is_err());
+    }
+}
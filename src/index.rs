@@ -0,0 +1,245 @@
+use std::error::Error;
+use crate::map::{self, Difficulty, OverworldMap};
+
+/// The features recorded for each seed, by legend letter. The elevator is placed twice so it
+/// gets two slots.
+pub const INDEX_FEATURES: &str = "@RMFTGVS$Cee";
+
+/// What a runner can see early on for one seed: the base map and rotation, and where each of
+/// INDEX_FEATURES is, plus the two halves of the shortest route once it's been worked out.
+/// Results databases are made of these.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SeedRecord {
+    pub seed: u32,
+    pub base_map: u8,
+    pub base_rotation: u8,
+    pub positions: [Option<(u8, u8)>; INDEX_FEATURES.len()],
+    /// First and last half of the shortest route, None if it wasn't worked out
+    pub route: Option<(u16, u16)>,
+}
+
+impl SeedRecord {
+    pub fn from_map(seed: u32, map: &OverworldMap) -> SeedRecord {
+        let mut positions = [None; INDEX_FEATURES.len()];
+        let found = map.feature_positions();
+        let mut taken = Vec::new();
+        for (slot, c) in INDEX_FEATURES.chars().enumerate() {
+            // The second 'e' slot gets the second elevator
+            let next = found.iter().enumerate().find(|(i, f)| f.0 == c && !taken.contains(i));
+            if let Some((i, &(_, (x, y)))) = next {
+                taken.push(i);
+                positions[slot] = Some((x as u8, y as u8));
+            }
+        }
+        let info = map.info();
+        SeedRecord { seed, base_map: info.base_map as u8, base_rotation: info.base_rotation, positions, route: None }
+    }
+
+    /// The record for a seed that didn't generate
+    pub fn empty(seed: u32) -> SeedRecord {
+        SeedRecord { seed, base_map: 0, base_rotation: 0, positions: [None; INDEX_FEATURES.len()], route: None }
+    }
+
+    /// Every position recorded for a legend letter (two for the elevators)
+    pub fn positions_of(&self, c: char) -> Vec<(usize, usize)> {
+        INDEX_FEATURES.chars().zip(self.positions.iter())
+            .filter_map(|(f, p)| if f == c { p.map(|(x, y)| (x as usize, y as usize)) } else { None })
+            .collect()
+    }
+}
+
+pub(crate) fn difficulty_byte(d: Difficulty) -> u8 {
+    match d {
+        Difficulty::Easy => 0,
+        Difficulty::Medium => 1,
+        Difficulty::Hard => 2,
+        Difficulty::PRO => 3,
+    }
+}
+
+pub(crate) fn byte_difficulty(b: u8) -> Difficulty {
+    match b {
+        1 => Difficulty::Medium,
+        2 => Difficulty::Hard,
+        3 => Difficulty::PRO,
+        _ => Difficulty::Easy,
+    }
+}
+
+// Distance along one axis of the wrapping map
+pub(crate) fn axis_distance(a: usize, b: usize) -> usize {
+    let d = a.abs_diff(b);
+    d.min(50 - d.min(50))
+}
+
+// How far `to` is from `from` along one axis, going the short way round: -25..=25
+fn axis_offset(from: usize, to: usize) -> i64 {
+    let d = (to as i64 - from as i64).rem_euclid(50);
+    if d > 25 { d - 50 } else { d }
+}
+
+/// Which part of the shortest route a Constraint::Route is about
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RoutePart {
+    First,
+    Last,
+    Total,
+}
+
+/// One thing known about a map
+#[derive(Debug, PartialEq, Clone)]
+pub enum Constraint {
+    BaseMap(u8),
+    Rotation(u8),
+    /// A feature (legend letter) within `tolerance` tiles of (x, y) on both axes
+    At(char, (usize, usize), usize),
+    /// The second feature is (dx, dy) from the first, give or take `tolerance` on both axes
+    Offset(char, char, (i64, i64), usize),
+    /// The part of the route is between the two lengths, inclusive. A record without a route
+    /// never matches.
+    Route(RoutePart, u16, u16),
+}
+
+impl Constraint {
+    pub fn matches(&self, r: &SeedRecord) -> bool {
+        match self {
+            Constraint::BaseMap(b) => r.base_map == *b,
+            Constraint::Rotation(rot) => r.base_rotation == *rot,
+            Constraint::At(c, (x, y), tol) => r.positions_of(*c).iter()
+                .any(|&(fx, fy)| axis_distance(fx, *x) <= *tol && axis_distance(fy, *y) <= *tol),
+            Constraint::Offset(from, to, (dx, dy), tol) => {
+                let (froms, tos) = (r.positions_of(*from), r.positions_of(*to));
+                froms.iter().any(|&(fx, fy)| tos.iter().any(|&(tx, ty)| {
+                    (axis_offset(fx, tx) - dx).unsigned_abs() as usize <= *tol
+                        && (axis_offset(fy, ty) - dy).unsigned_abs() as usize <= *tol
+                }))
+            }
+            Constraint::Route(part, low, high) => r.route.is_some_and(|(first, last)| {
+                let length = match part {
+                    RoutePart::First => first,
+                    RoutePart::Last => last,
+                    RoutePart::Total => first + last,
+                };
+                (*low..=*high).contains(&length)
+            }),
+        }
+    }
+}
+
+/// A set of constraints that all have to hold, parsed from space separated terms:
+///   base=4          the base map
+///   rot=2           the base rotation
+///   V=12,30         the volcano is at (12, 30)
+///   V=12,30~2       ... give or take 2 tiles
+///   C-@=5,-3~1      the start is 5 east and 3 north of the castle tablet, give or take 1
+///   last=9          the last half of the route (V-S-C) is 9, first= and total= for the others.
+///                   ~ works here too, and last<=9 or total>=40 for at most/at least
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Query {
+    pub constraints: Vec<Constraint>,
+}
+
+impl Query {
+    pub fn parse(text: &str) -> Result<Query, Box<dyn Error>> {
+        let mut constraints = Vec::new();
+        for term in text.split_whitespace() {
+            let (lhs, rhs) = term.split_once('=').ok_or(format!("'{}' should look like name=value", term))?;
+            let (lhs, bound) = match lhs.strip_suffix(['<', '>']) {
+                Some(name) => (name, lhs.chars().last()),
+                None => (lhs, None),
+            };
+            let (value, tolerance) = match rhs.split_once('~') {
+                Some((v, t)) => (v, t.parse::<usize>()?),
+                None => (rhs, 0),
+            };
+            let pair = |v: &str| -> Result<(i64, i64), Box<dyn Error>> {
+                let (a, b) = v.split_once(',').ok_or(format!("'{}' should be x,y", v))?;
+                Ok((a.parse()?, b.parse()?))
+            };
+            let letter = |s: &str| -> Result<char, Box<dyn Error>> {
+                let c = s.chars().next().filter(|_| s.chars().count() == 1).ok_or(format!("'{}' isn't a legend letter", s))?;
+                map::symbol_tile_id(c).ok_or(format!("'{}' isn't a legend letter", s))?;
+                Ok(c)
+            };
+            let part = match lhs {
+                "first" => Some(RoutePart::First),
+                "last" => Some(RoutePart::Last),
+                "total" => Some(RoutePart::Total),
+                _ => None,
+            };
+            if let Some(part) = part {
+                let length: u16 = value.parse()?;
+                let tolerance = tolerance as u16;
+                constraints.push(match bound {
+                    Some('<') => Constraint::Route(part, 0, length),
+                    Some(_) => Constraint::Route(part, length, u16::MAX),
+                    None => Constraint::Route(part, length.saturating_sub(tolerance), length.saturating_add(tolerance)),
+                });
+                continue;
+            }
+            if bound.is_some() {
+                return Err(format!("'{}': only first, last and total can be compared with <= or >=", term).into());
+            }
+            constraints.push(match lhs {
+                "base" => Constraint::BaseMap(value.parse()?),
+                "rot" => Constraint::Rotation(value.parse()?),
+                _ => match lhs.split_once('-') {
+                    Some((from, to)) => Constraint::Offset(letter(from)?, letter(to)?, pair(value)?, tolerance),
+                    None => {
+                        let (x, y) = pair(value)?;
+                        if !(0..50).contains(&x) || !(0..50).contains(&y) {
+                            return Err(format!("'{}' is off the map", value).into());
+                        }
+                        Constraint::At(letter(lhs)?, (x as usize, y as usize), tolerance)
+                    }
+                },
+            });
+        }
+        Ok(Query { constraints })
+    }
+
+    pub fn matches(&self, r: &SeedRecord) -> bool {
+        self.constraints.iter().all(|c| c.matches(r))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(seed: u32) -> SeedRecord {
+        let mut positions = [None; INDEX_FEATURES.len()];
+        positions[0] = Some((48, 10)); // @
+        positions[6] = Some((12, 30)); // V
+        positions[9] = Some((2, 12)); // C
+        positions[10] = Some((20, 20)); // e
+        positions[11] = Some((40, 40)); // e
+        SeedRecord { seed, base_map: 4, base_rotation: 2, positions, route: None }
+    }
+
+    #[test]
+    fn queries() {
+        let r = record(7);
+        let yes = ["base=4 rot=2", "V=12,30", "V=10,32~2", "e=40,40", "C-@=-4,-2", "C-@=-3,-1~1", "@=1,10~3"];
+        let no = ["base=3", "V=10,32~1", "e=30,30~5", "C-@=4,-2", "$=0,0~49"];
+        for q in yes.iter() {
+            assert!(Query::parse(q).unwrap().matches(&r), "{}", q);
+        }
+        for q in no.iter() {
+            assert!(!Query::parse(q).unwrap().matches(&r), "{}", q);
+        }
+        assert!(Query::parse("V=50,0").is_err());
+        assert!(Query::parse("X=1,1").is_err());
+        assert!(Query::parse("V").is_err());
+
+        assert!(!Query::parse("last=9").unwrap().matches(&r));
+        let r = SeedRecord { route: Some((30, 9)), ..r };
+        for q in ["last=9", "last<=9", "total>=39", "first=28~2", "total=39 base=4"] {
+            assert!(Query::parse(q).unwrap().matches(&r), "{}", q);
+        }
+        for q in ["last=8", "last<=8", "total>=40", "first=27~2"] {
+            assert!(!Query::parse(q).unwrap().matches(&r), "{}", q);
+        }
+        assert!(Query::parse("V<=1,1").is_err());
+    }
+}
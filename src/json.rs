@@ -0,0 +1,335 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+
+// Deeper than anything we write by a long way, and shallow enough not to run out of stack
+const MAX_DEPTH: usize = 128;
+
+/// Just enough JSON to read and write our own map and results files without pulling in a
+/// whole serialization library. Objects keep their keys sorted so output is stable.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(o) => o.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Some(*n as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Number(n) if n.fract() == 0.0 => Some(*n as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Value>> {
+        match self {
+            Value::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&BTreeMap<String, Value>> {
+        match self {
+            Value::Object(o) => Some(o),
+            _ => None,
+        }
+    }
+
+    /// Writes the value out compactly (no whitespace). JSON has no NaN or infinity, so a
+    /// number that isn't finite is an error.
+    pub fn to_json(&self) -> Result<String, Box<dyn Error>> {
+        let mut out = String::new();
+        self.write(&mut out)?;
+        Ok(out)
+    }
+
+    fn write(&self, out: &mut String) -> Result<(), Box<dyn Error>> {
+        match self {
+            Value::Null => out.push_str("null"),
+            Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Value::Number(n) if !n.is_finite() => return Err(format!("Can't write {} as JSON", n).into()),
+            Value::Number(n) => {
+                if n.fract() == 0.0 && n.abs() < 1e15 {
+                    out.push_str(&format!("{}", *n as i64));
+                } else {
+                    out.push_str(&format!("{}", n));
+                }
+            }
+            Value::String(s) => write_string(s, out),
+            Value::Array(a) => {
+                out.push('[');
+                for (i, v) in a.iter().enumerate() {
+                    if i > 0 { out.push(','); }
+                    v.write(out)?;
+                }
+                out.push(']');
+            }
+            Value::Object(o) => {
+                out.push('{');
+                for (i, (k, v)) in o.iter().enumerate() {
+                    if i > 0 { out.push(','); }
+                    write_string(k, out);
+                    out.push(':');
+                    v.write(out)?;
+                }
+                out.push('}');
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<u64> for Value {
+    fn from(n: u64) -> Self { Value::Number(n as f64) }
+}
+
+impl From<usize> for Value {
+    fn from(n: usize) -> Self { Value::Number(n as f64) }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self { Value::Number(n as f64) }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self { Value::String(s.to_string()) }
+}
+
+fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Parses a complete JSON document
+pub fn parse(text: &str) -> Result<Value, Box<dyn Error>> {
+    let mut parser = Parser { chars: text.chars().collect(), pos: 0, depth: 0 };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos != parser.chars.len() {
+        return Err(format!("Unexpected trailing characters at {}", parser.pos).into());
+    }
+    Ok(value)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.chars.len() && self.chars[self.pos].is_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn next(&mut self) -> Result<char, Box<dyn Error>> {
+        let c = *self.chars.get(self.pos).ok_or("Unexpected end of JSON")?;
+        self.pos += 1;
+        Ok(c)
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), Box<dyn Error>> {
+        for w in word.chars() {
+            if self.next()? != w {
+                return Err(format!("Expected '{}' at {}", word, self.pos).into());
+            }
+        }
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Value, Box<dyn Error>> {
+        if self.depth == MAX_DEPTH {
+            return Err(format!("JSON nested more than {} deep at {}", MAX_DEPTH, self.pos).into());
+        }
+        self.depth += 1;
+        let value = self.nested_value();
+        self.depth -= 1;
+        value
+    }
+
+    fn nested_value(&mut self) -> Result<Value, Box<dyn Error>> {
+        self.skip_whitespace();
+        match self.chars.get(self.pos) {
+            Some('n') => { self.expect("null")?; Ok(Value::Null) }
+            Some('t') => { self.expect("true")?; Ok(Value::Bool(true)) }
+            Some('f') => { self.expect("false")?; Ok(Value::Bool(false)) }
+            Some('"') => Ok(Value::String(self.string()?)),
+            Some('[') => {
+                self.pos += 1;
+                let mut array = Vec::new();
+                self.skip_whitespace();
+                if self.chars.get(self.pos) == Some(&']') {
+                    self.pos += 1;
+                    return Ok(Value::Array(array));
+                }
+                loop {
+                    array.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.next()? {
+                        ',' => continue,
+                        ']' => return Ok(Value::Array(array)),
+                        c => return Err(format!("Unexpected '{}' in array at {}", c, self.pos).into()),
+                    }
+                }
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut object = BTreeMap::new();
+                self.skip_whitespace();
+                if self.chars.get(self.pos) == Some(&'}') {
+                    self.pos += 1;
+                    return Ok(Value::Object(object));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    if self.next()? != ':' {
+                        return Err(format!("Expected ':' at {}", self.pos).into());
+                    }
+                    let value = self.value()?;
+                    object.insert(key, value);
+                    self.skip_whitespace();
+                    match self.next()? {
+                        ',' => continue,
+                        '}' => return Ok(Value::Object(object)),
+                        c => return Err(format!("Unexpected '{}' in object at {}", c, self.pos).into()),
+                    }
+                }
+            }
+            Some(_) => self.number(),
+            None => Err("Unexpected end of JSON".into()),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, Box<dyn Error>> {
+        if self.next()? != '"' {
+            return Err(format!("Expected a string at {}", self.pos).into());
+        }
+        let mut s = String::new();
+        loop {
+            match self.next()? {
+                '"' => return Ok(s),
+                '\\' => match self.next()? {
+                    '"' => s.push('"'),
+                    '\\' => s.push('\\'),
+                    '/' => s.push('/'),
+                    'b' => s.push('\u{8}'),
+                    'f' => s.push('\u{c}'),
+                    'n' => s.push('\n'),
+                    'r' => s.push('\r'),
+                    't' => s.push('\t'),
+                    'u' => {
+                        let mut code = self.hex4()?;
+                        // Anything past the BMP comes as a surrogate pair, high half first
+                        if (0xd800..0xdc00).contains(&code) {
+                            let low = if self.next()? == '\\' && self.next()? == 'u' { self.hex4()? } else { 0 };
+                            if !(0xdc00..0xe000).contains(&low) {
+                                return Err(format!("Unpaired surrogate in \\u escape at {}", self.pos).into());
+                            }
+                            code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                        }
+                        s.push(char::from_u32(code).ok_or("Invalid \\u escape")?);
+                    }
+                    c => return Err(format!("Invalid escape '\\{}'", c).into()),
+                },
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, Box<dyn Error>> {
+        let hex: String = (0..4).map(|_| self.next()).collect::<Result<_, _>>()?;
+        Ok(u32::from_str_radix(&hex, 16)?)
+    }
+
+    fn number(&mut self) -> Result<Value, Box<dyn Error>> {
+        let start = self.pos;
+        while self.pos < self.chars.len() && matches!(self.chars[self.pos], '-' | '+' | '.' | 'e' | 'E' | '0'..='9') {
+            self.pos += 1;
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        match text.parse::<f64>() {
+            Ok(n) if n.is_finite() => Ok(Value::Number(n)),
+            Ok(_) => Err(format!("Number out of range at {}", start).into()),
+            Err(_) => Err(format!("Invalid value at {}", start).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let text = r#"{"a":[1,-2,3.5,true,null],"b":"q\"u♂","c":{}}"#;
+        let value = parse(text).unwrap();
+        assert!(value.get("a").unwrap().as_array().unwrap()[1].as_i64() == Some(-2));
+        assert!(value.get("b").unwrap().as_str() == Some("q\"u♂"));
+        assert!(value.to_json().unwrap() == text);
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(parse("{\"a\":}").is_err());
+        assert!(parse("[1,2").is_err());
+        assert!(parse("1 2").is_err());
+        assert!(parse("1e999").is_err());
+        assert!(Value::Number(f64::NAN).to_json().is_err());
+        assert!(Value::Array(vec![Value::Number(f64::INFINITY)]).to_json().is_err());
+    }
+
+    #[test]
+    fn surrogate_pairs() {
+        assert!(parse(r#""\ud83d\ude00""#).unwrap().as_str() == Some("\u{1f600}"));
+        assert!(parse(r#""\ud83d""#).is_err());
+        assert!(parse(r#""\ud83dx""#).is_err());
+        assert!(parse(r#""\ude00""#).is_err());
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let nested = |depth: usize| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(parse(&nested(MAX_DEPTH)).is_ok());
+        assert!(parse(&nested(MAX_DEPTH + 1)).is_err());
+        assert!(parse(&nested(100_000)).is_err());
+    }
+}
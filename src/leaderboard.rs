@@ -0,0 +1,176 @@
+use std::collections::{BTreeMap, BinaryHeap};
+use std::error::Error;
+use crate::json::Value;
+use crate::map::{OverworldMap, Route};
+use crate::random::VHRandom;
+
+/// What a leaderboard ranks maps by. Lower is better for all of them.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Metric {
+    /// The whole shortest route, what the under50 filter and option 5 go by
+    #[default]
+    Total,
+    /// Start to the sword, through the first half of the dungeons
+    FirstHalf,
+    /// V-S-C, what the last3 filter goes by
+    LastHalf,
+}
+
+impl Metric {
+    pub fn parse(s: &str) -> Result<Metric, Box<dyn Error>> {
+        match s {
+            "total" => Ok(Metric::Total),
+            "first" => Ok(Metric::FirstHalf),
+            "last" => Ok(Metric::LastHalf),
+            _ => Err(format!("Unknown metric '{}', it's total, first or last", s).into()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Metric::Total => "total",
+            Metric::FirstHalf => "first",
+            Metric::LastHalf => "last",
+        }
+    }
+
+    /// The map gets passed too so a metric can look at more than the route
+    pub fn score(&self, _map: &OverworldMap, route: &Route) -> u16 {
+        match self {
+            Metric::Total => route.first_half + route.last_half,
+            Metric::FirstHalf => route.first_half,
+            Metric::LastHalf => route.last_half,
+        }
+    }
+}
+
+/// A map on the leaderboard. Ordered by score and then seed, so ties always go to the lower seed
+/// and the board comes out the same however the sweep was split up.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord)]
+pub struct Entry {
+    pub score: u16,
+    pub seed: u32,
+    pub first_half: u16,
+    pub last_half: u16,
+    pub base_map: u32,
+}
+
+impl Entry {
+    pub fn new(metric: Metric, map: &OverworldMap, route: &Route) -> Entry {
+        let info = map.info();
+        Entry { score: metric.score(map, route), seed: info.seed, first_half: route.first_half, last_half: route.last_half, base_map: info.base_map }
+    }
+
+    pub fn code(&self) -> String {
+        VHRandom::from_seed(self.seed).get_code()
+    }
+}
+
+/// The best `size` maps seen so far. It's a max-heap on the entries, so the worst of the kept
+/// ones is on top and can be dropped as soon as something better comes along, and memory stays
+/// the same however long the sweep runs.
+#[derive(Debug, Clone, Default)]
+pub struct Leaderboard {
+    size: usize,
+    heap: BinaryHeap<Entry>,
+}
+
+impl PartialEq for Leaderboard {
+    fn eq(&self, other: &Leaderboard) -> bool {
+        self.size == other.size && self.entries() == other.entries()
+    }
+}
+
+impl Leaderboard {
+    pub fn new(size: usize) -> Leaderboard {
+        Leaderboard { size, heap: BinaryHeap::with_capacity(size + 1) }
+    }
+
+    /// Adds the entry if it's better than the worst one kept, or there's still room. Returns
+    /// true if it made it on.
+    pub fn offer(&mut self, entry: Entry) -> bool {
+        if self.heap.len() < self.size {
+            self.heap.push(entry);
+            return true;
+        }
+        match self.heap.peek() {
+            Some(worst) if entry < *worst => {
+                self.heap.pop();
+                self.heap.push(entry);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn size(&self) -> usize { self.size }
+
+    /// Best first
+    pub fn entries(&self) -> Vec<Entry> {
+        let mut entries = self.heap.clone().into_vec();
+        entries.sort();
+        entries
+    }
+
+    /// One tab separated line per map, best first, after a header
+    pub fn to_text(&self, metric: Metric) -> String {
+        let mut out = format!("rank\t{}\tfirst_half\tlast_half\tcode\tseed\tbase_map\n", metric.name());
+        for (rank, e) in self.entries().iter().enumerate() {
+            out += &format!("{}\t{}\t{}\t{}\t{}\t{:#010x}\t{}\n", rank + 1, e.score, e.first_half, e.last_half, e.code(), e.seed, e.base_map);
+        }
+        out
+    }
+
+    /// The entries best first, as written to checkpoints and by --format json. The code is
+    /// only there for people reading it, the seed is what's read back.
+    pub fn to_json_value(&self) -> Value {
+        Value::Array(self.entries().iter().map(|e| {
+            let mut o = BTreeMap::new();
+            o.insert("score".to_string(), (e.score as u64).into());
+            o.insert("seed".to_string(), (e.seed as u64).into());
+            o.insert("code".to_string(), e.code().as_str().into());
+            o.insert("first_half".to_string(), (e.first_half as u64).into());
+            o.insert("last_half".to_string(), (e.last_half as u64).into());
+            o.insert("base_map".to_string(), (e.base_map as u64).into());
+            Value::Object(o)
+        }).collect())
+    }
+
+    pub fn from_json_value(size: usize, v: &Value) -> Result<Leaderboard, Box<dyn Error>> {
+        let mut board = Leaderboard::new(size);
+        for e in v.as_array().ok_or("Leaderboard should be an array")? {
+            let number = |name: &str| e.get(name).and_then(Value::as_u64).ok_or(format!("Leaderboard entry is missing '{}'", name));
+            board.offer(Entry {
+                score: number("score")? as u16,
+                seed: number("seed")? as u32,
+                first_half: number("first_half")? as u16,
+                last_half: number("last_half")? as u16,
+                base_map: number("base_map")? as u32,
+            });
+        }
+        Ok(board)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(score: u16, seed: u32) -> Entry {
+        Entry { score, seed, first_half: score / 2, last_half: score - score / 2, base_map: 4 }
+    }
+
+    #[test]
+    fn keeps_the_best() {
+        let mut board = Leaderboard::new(3);
+        for (score, seed) in [(50, 1), (40, 2), (60, 3), (40, 0), (45, 4), (70, 5)] {
+            board.offer(entry(score, seed));
+        }
+        assert!(board.entries() == vec![entry(40, 0), entry(40, 2), entry(45, 4)]);
+        assert!(!board.offer(entry(45, 9)) && board.offer(entry(45, 3)));
+        assert!(Leaderboard::from_json_value(3, &board.to_json_value()).unwrap() == board);
+        assert!(board.to_text(Metric::Total).lines().nth(1).unwrap().starts_with("1\t40\t20\t20\t"));
+        assert!(Leaderboard::new(0).entries().is_empty() && !Leaderboard::new(0).offer(entry(1, 1)));
+        assert!(Metric::parse("last").unwrap() == Metric::LastHalf && Metric::parse("shortest").is_err());
+    }
+}
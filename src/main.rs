@@ -1,102 +1,103 @@
 ﻿pub mod random;
 pub mod map;
+pub mod json;
+pub mod export;
+pub mod search;
+pub mod index;
+pub mod disc;
+pub mod basemaps;
+pub mod cli;
+pub mod sweep;
+pub mod checkpoint;
+pub mod leaderboard;
+pub mod results;
+pub mod merge;
+pub mod filter;
 
-use std::error::Error;
-use std::thread;
 use std::time::Instant;
 use map::_FCargs;
-use std::collections::hash_map::HashMap;
 use std::fs;
-use std::io;
-use std::ffi::OsString;
-use std::ffi::OsStr;
+use std::error::Error;
 
-#[macro_use(fcargs)]
+fn print_legend() {
+    println!("Legend");
+    println!("\x1b[93;100mG - Graveyard\tM - Mansion\tT - Trial\x1b[0m");
+    println!("\x1b[93;100mR - Ruins\tV - Volcano\tF - Fairy\x1b[0m");
+    println!("\x1b[93;100mC - Castle Tablet\t@ - Start\t$ - Shop\x1b[0m");
+    println!("\x1b[93;100mS - Sealed\t\x1b[35;100mT - Transport Crystals\x1b[0m");
+    println!("\x1b[32mh - herbs\ta - antidotes\tp - poison herbs\te - elevator\x1b[0m");
+}
 
-fn map_iteration(count: u32, start: u32, difficulty: map::Difficulty, winnow: &Vec<bool>, save: bool) ->  Result<bool, Box<dyn Error>> {
-    
-    let now = Instant::now();    
+fn warn_about_base_maps() {
+    for (n, status) in basemaps::check_base_maps() {
+        if status.is_problem() {
+            println!("Warning: {}", status.describe(n));
+        }
+    }
+}
 
-    thread::scope(|s| {                    
-        let t1 = s.spawn(|| {
-            for i in start..(start + count/4) {
-                let code = &random::VHRandom::from_seed(i).get_code();
-                let m = match map::OverworldMap::from_code(&fcargs!(&code, difficulty, winnow.to_vec())) {
-                    Ok(m) => Some(m),
-                    Err(_) => None,
-                };                
-                if save && m.is_some() {
-                    m.unwrap().save_map(&code).unwrap(); 
-                }
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = cli::run(&args) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+// The summary goes to stderr when there are records, in case they're on stdout. A leaderboard
+// is printed and written to leaderboard.txt in the output directory.
+fn print_sweep_result(result: Result<(sweep::SweepConfig, sweep::SweepStats), Box<dyn Error>>, has_records: bool, checkpoint: &str, output_dir: &str) {
+    match result {
+        Ok((config, stats)) => {
+            let mut summary = stats.summary(&config);
+            if !stats.finished {
+                summary += &format!(", carry on with option 19 and {}", checkpoint);
             }
-        });
-    
-        let t2 = s.spawn(|| {
-            for i in (start + count/4)..(start + count/2) {
-                let code = &random::VHRandom::from_seed(i).get_code();
-                let m = match map::OverworldMap::from_code(&fcargs!(&code, difficulty, winnow.to_vec())) {
-                    Ok(m) => Some(m),
-                    Err(_) => None,
-                };
-                if save && m.is_some() {
-                    m.unwrap().save_map(&code).unwrap(); 
-                }
+            let breakdown = stats.breakdown(&config);
+            if !breakdown.is_empty() {
+                summary += &format!("\n{}", breakdown);
             }
-        });
-    
-        let t3 = s.spawn(|| {
-            for i in (start + count/2)..(start + count/4*3) {
-                let code = &random::VHRandom::from_seed(i).get_code();
-                let m = match map::OverworldMap::from_code(&fcargs!(&code, difficulty, winnow.to_vec())) {
-                    Ok(m) => Some(m),
-                    Err(_) => None,
-                };
-                if save && m.is_some() {
-                    m.unwrap().save_map(&code).unwrap(); 
+            if has_records { eprintln!("{}", summary) } else { println!("{}", summary) }
+            if let Some((metric, _)) = config.top {
+                let text = stats.progress.leaderboard.to_text(metric);
+                let path = std::path::Path::new(output_dir).join("leaderboard.txt");
+                if has_records { eprint!("{}", text) } else { print!("{}", text) }
+                match fs::create_dir_all(output_dir).and_then(|_| fs::write(&path, text)) {
+                    Ok(_) => println!("Leaderboard written to {}", path.display()),
+                    Err(e) => println!("Couldn't write the leaderboard to {}: {}", path.display(), e),
                 }
             }
-        });
-
-        t1.join().unwrap();
-        t2.join().unwrap();
-        t3.join().unwrap();
-    });
-        for i in (start + count/4*3)..(start + count) {
-                let code = &random::VHRandom::from_seed(i).get_code();
-                let m = match map::OverworldMap::from_code(&fcargs!(&code, difficulty, winnow.to_vec())) {
-                    Ok(m) => Some(m),
-                    Err(_) => None,
-                };
-                if save && m.is_some() {
-                    m.unwrap().save_map(&code).unwrap(); 
-                }
-        }
-    
-        let elapsed = now.elapsed();
-        let mut savestring = "";
-        if save {
-            savestring = ", saved ";
-        }
-        let mut winnowedstring = String::from("");
-        for i in 0..winnow.len() {
-            if winnow[i] {
-              winnowedstring = winnowedstring + &i.to_string() + "w ";
-            }
         }
-        println!("{} maps generated {}{}in {} seconds", count, winnowedstring, savestring, elapsed.as_secs_f64());
-        return Ok(true);
+        Err(e) => println!("The sweep failed: {}", e),
+    }
 }
 
-fn main() {
-    let mut difficulty:map::Difficulty = map::Difficulty::Easy;    
+/// The interactive menu, the `menu` command (and the default with no command)
+fn menu(mut difficulty: map::Difficulty, mut output_dir: String) {
+    warn_about_base_maps();
     loop {
         let mut line = String::new();
         println!("Virtual Hydlide Map Generation Toolkit v2.4.0");
         println!("1 to set the difficulty, currently {}", map::difficulty_text(&difficulty));
-        println!("2 for generating and printing a specific seed");    
+        println!("2 for generating and printing a specific seed, with its best route marked");    
         println!("3 to generate ascii for all 5 base maps");
         println!("4 to generate a given number of seeds, possibly winnowing the results out and saving only the remainder.");
-        println!("5 to return the maps in /genmaps in shortest path order");
+        println!("5 to return the maps in {} in shortest path order", output_dir);
+        println!("6 to print a seed recentred on the start (or another feature), the way the wrapping map plays");
+        println!("7 to compare two seeds (or one seed on two difficulties) side by side");
+        println!("8 to save a seed as JSON and Tiled TMX in {}", output_dir);
+        println!("9 to print a Mednafen RAM dump of the overworld and list its features");
+        println!("10 to check a Mednafen RAM dump against the map generated for a code");
+        println!("11 to search for the seeds that make a Mednafen RAM dump");
+        println!("12 to build an index of what seeds look like, for looking them up later");
+        println!("13 to look up seeds in an index from what you've seen in game (reads the whole file)");
+        println!("14 to write a seed (or an edited JSON map) as a Mednafen RAM dump for injecting");
+        println!("15 to get the base maps from a different directory or a disc image (.iso, or .bin/.cue), currently {}", basemaps::source());
+        println!("16 to check the base maps against the known retail checksums");
+        println!("17 to change the directory maps are saved to and ranked from");
+        println!("18 to generate on your own base maps instead of the retail ones");
+        println!("19 to carry on a sweep from a checkpoint file");
+        println!("20 to look seeds up in a results database written by option 4");
         println!("Anything else to quit or crash.");
         let _bytecount = std::io::stdin().read_line(&mut line).unwrap();
         println!("{}",line);
@@ -106,17 +107,10 @@ fn main() {
             println!("♂ is Alt-11, ♀ is Alt-12");
             let mut line2 = String::new();
             let _seedcount = std::io::stdin().read_line(&mut line2).unwrap();            
-            let mut str_line = line2.as_str().strip_suffix("\n").unwrap();            
+            let str_line = line2.as_str().strip_suffix("\n").unwrap();            
             let result_map= map::OverworldMap::from_code(&fcargs!(str_line, difficulty));
             match result_map {
-                Ok(map) => { map.print_map();
-                println!("Legend");
-                println!("\x1b[93;100m{}\t{}\t{}\x1b[0m","G - Graveyard","M - Mansion", "T - Trial");
-                println!("\x1b[93;100m{}\t{}\t{}\x1b[0m","R - Ruins","V - Volcano", "F - Fairy");
-                println!("\x1b[93;100m{}\t{}\t{}\x1b[0m", "C - Castle Tablet", "@ - Start", "$ - Shop");
-                println!("\x1b[93;100m{}\t\x1b[35;100m{}\x1b[0m", "S - Sealed", "T - Transport Crystals");
-                println!("\x1b[32m{}\t{}\t{}\t{}\x1b[0m", "h - herbs", "a - antidotes", "p - poison herbs","e - elevator");
-                },
+                Ok(map) => { map.print_route_map(); print_legend(); },
                 Err(e) => { println!("{}", e); println!("Please enter exactly 10 characters next time. Spaces count!"); }
             }
         }
@@ -143,73 +137,366 @@ fn main() {
         }
         else if choice == 4 {
             let mut save = false;
-            let mut winnow = Vec::from([false, false, false]);
             println!("Enter what number you want to start on");
             let mut line_start = String::new();
             let mut _count = std::io::stdin().read_line(&mut line_start).unwrap();        
             let start = line_start.trim_end().parse::<u32>().unwrap();
-            println!("Enter the number of iterations you want");
+            println!("Enter the number of iterations you want (up to 4294967296, it wraps around to 0 after the last seed)");
             let mut line2 = String::new();
             _count = std::io::stdin().read_line(&mut line2).unwrap();        
-            let iterations = line2.trim_end().parse::<u32>().unwrap();
-            println!("Do you want to winnow based on...");
-            println!("... not being Map 4? (Y/N)");
-            let mut line3 = String::new();
-            _count = std::io::stdin().read_line(&mut line3).unwrap();                    
-            if line3.trim_end() == "Y" {
-                winnow[0] = true;                
-            }
-            println!("... not having a shortest possible V-S-C? (Y/N)");
-            line3 = String::new();
-            _count = std::io::stdin().read_line(&mut line3).unwrap();                    
-            if line3.trim_end() == "Y" {                
-                winnow[1] = true;               
-            }
-            println!("... not having a short overall path? (Y/N)");
-            line3 = String::new();
-            _count = std::io::stdin().read_line(&mut line3).unwrap();                    
-            if line3.trim_end() == "Y" {                
-                winnow[2] = true;               
-            }
+            let iterations = line2.trim_end().parse::<u64>().unwrap();
+            println!("Enter a filter to winnow with, like: base == 4 && last <= 9 && dist(@, $) < 8");
+            println!("or any of the old methods by name: map4 (Map 4), last3 (shortest possible V-S-C), under50 (short overall path), e.g. map4,under50. Leave it blank not to winnow");
+            let filter = loop {
+                let mut line3 = String::new();
+                _count = std::io::stdin().read_line(&mut line3).unwrap();
+                match filter::Expression::parse_filter(line3.trim()) {
+                    Ok(filter) => break filter,
+                    Err(e) => println!("{}. Try again", e),
+                }
+            };
             println!("Enter Y to save (9K per file, do the math)");
             let mut line4 = String::new();
             _count = std::io::stdin().read_line(&mut line4).unwrap();        
             if line4.trim_end() == "Y" {
                 save = true;
             }
-            map_iteration(iterations, start, difficulty, &winnow, save).unwrap();
+            println!("Enter csv or jsonl to print a line for each seed that's kept, optionally followed by a file to write them to instead. Anything else for no output");
+            let mut line5 = String::new();
+            _count = std::io::stdin().read_line(&mut line5).unwrap();
+            let mut words = line5.split_whitespace();
+            let records = match words.next().map(sweep::RecordFormat::parse) {
+                Some(Ok(format)) => match sweep::RecordWriter::open(format, words.next()) {
+                    Ok(writer) => Some(writer),
+                    Err(e) => { println!("Couldn't write the records: {}", e); None },
+                },
+                _ => None,
+            };
+            println!("Enter how many of the best seeds to keep in a leaderboard, optionally followed by what to rank them by: total (the default), first or last (the two halves of the route). Leave it blank not to");
+            let mut line7 = String::new();
+            _count = std::io::stdin().read_line(&mut line7).unwrap();
+            let mut words = line7.split_whitespace();
+            let top = match (words.next().map(str::parse::<usize>), words.next().map_or(Ok(leaderboard::Metric::Total), leaderboard::Metric::parse)) {
+                (Some(Ok(size)), Ok(metric)) if size > 0 => Some((metric, size)),
+                (None, _) => None,
+                _ => { println!("That's not a leaderboard size and metric, so there won't be one"); None },
+            };
+            println!("Enter a file to write a results database of every seed to, for looking them up with option 20 without generating them again. Only without a filter. Leave it blank not to");
+            let mut line8 = String::new();
+            _count = std::io::stdin().read_line(&mut line8).unwrap();
+            let database = Some(line8.trim().to_string()).filter(|path| !path.is_empty());
+            println!("Enter a file to checkpoint to, so the sweep can be stopped with Ctrl-C and carried on with option 19. Leave it blank not to");
+            let mut line6 = String::new();
+            _count = std::io::stdin().read_line(&mut line6).unwrap();
+            let checkpoint = Some(line6.trim()).filter(|path| !path.is_empty());
+            let result = sweep::SeedRange::new(start, iterations).and_then(|range| {
+                let config = sweep::SweepConfig { range, difficulty, filter, save_dir: save.then(|| output_dir.clone()), top, database };
+                let stats = sweep::run_sweep(&config, sweep::default_threads(), records.as_ref(), checkpoint, true)?;
+                Ok((config, stats))
+            });
+            print_sweep_result(result, records.is_some(), checkpoint.unwrap_or(""), &output_dir);
         }
         else if choice == 5 {
-            // shortest_path dictionary empty
-            let mut path_lengths = HashMap::new();
-            for mapfile in fs::read_dir("./genmaps").expect("read dir call failed") {
-                if let Ok(mapfile) = mapfile {
-                    let mappath: std::path::PathBuf = mapfile.path();
-                    if mappath.is_dir() {
-                        continue;
+            match map::rank_saved_maps(&output_dir) {
+                Ok(ranked) => for (length, codes) in ranked {
+                    println!("{}", length);
+                    println!("{:?}", codes);
+                },
+                Err(e) => println!("Couldn't rank the maps in {}: {}", output_dir, e),
+            }
+        }
+        else if choice == 6 {
+            println!("Enter a seed string (10 characters)");
+            println!("♂ is Alt-11, ♀ is Alt-12");
+            let mut line2 = String::new();
+            let _seedcount = std::io::stdin().read_line(&mut line2).unwrap();
+            let str_line = line2.as_str().strip_suffix("\n").unwrap();
+            println!("Enter the legend letter of the feature to centre on (blank for the start)");
+            let mut line3 = String::new();
+            let _count = std::io::stdin().read_line(&mut line3).unwrap();
+            let symbol = line3.trim_end().chars().next().unwrap_or('@');
+            println!("Enter Y to add a coordinate ruler");
+            let mut line4 = String::new();
+            let _count = std::io::stdin().read_line(&mut line4).unwrap();
+            let ruler = line4.trim_end() == "Y";
+            match map::OverworldMap::from_code(&fcargs!(str_line, difficulty)) {
+                Ok(map) => {
+                    let center = map::symbol_tile_id(symbol).and_then(|id| map.find_tile(id));
+                    match center {
+                        Some(center) => { map.print_map_centered(center, ruler); print_legend(); },
+                        None => println!("There's no '{}' on this map to centre on.", symbol),
+                    }
+                },
+                Err(e) => { println!("{}", e); println!("Please enter exactly 10 characters next time. Spaces count!"); }
+            }
+        }
+        else if choice == 7 {
+            println!("Enter the first seed string (10 characters)");
+            let mut line2 = String::new();
+            let _count = std::io::stdin().read_line(&mut line2).unwrap();
+            let first_code = line2.as_str().strip_suffix("\n").unwrap();
+            println!("Enter the second seed string (blank to reuse the first)");
+            let mut line3 = String::new();
+            let _count = std::io::stdin().read_line(&mut line3).unwrap();
+            let mut second_code = line3.as_str().strip_suffix("\n").unwrap();
+            if second_code.is_empty() {
+                second_code = first_code;
+            }
+            println!("Enter the difficulty for the second map (blank for {})", map::difficulty_text(&difficulty));
+            let mut line4 = String::new();
+            let _count = std::io::stdin().read_line(&mut line4).unwrap();
+            let second_difficulty = if line4.trim_end().is_empty() { difficulty } else { map::text_difficulty(line4.trim_end()) };
+            let first = map::OverworldMap::from_code(&fcargs!(first_code, difficulty));
+            let second = map::OverworldMap::from_code(&fcargs!(second_code, second_difficulty));
+            match (first, second) {
+                (Ok(first), Ok(second)) => { first.print_diff(&second); print_legend(); },
+                (Err(e), _) | (_, Err(e)) => { println!("{}", e); println!("Please enter exactly 10 characters next time. Spaces count!"); }
+            }
+        }
+        else if choice == 8 {
+            println!("Enter a seed string (10 characters)");
+            let mut line2 = String::new();
+            let _count = std::io::stdin().read_line(&mut line2).unwrap();
+            let str_line = line2.as_str().strip_suffix("\n").unwrap();
+            match map::OverworldMap::from_code(&fcargs!(str_line, difficulty)) {
+                Ok(map) => match map.save_map_json(&output_dir, str_line).and(map.save_map_tmx(&output_dir, str_line)) {
+                    Ok(_) => println!("Saved {0}/{1}.json and {0}/{1}.tmx", output_dir, str_line),
+                    Err(e) => println!("Couldn't save: {}", e),
+                },
+                Err(e) => { println!("{}", e); println!("Please enter exactly 10 characters next time. Spaces count!"); }
+            }
+        }
+        else if choice == 9 {
+            println!("Enter the path to the dump file");
+            let mut line2 = String::new();
+            let _count = std::io::stdin().read_line(&mut line2).unwrap();
+            match map::load_mednafen_file(line2.trim_end()) {
+                Ok(map) => {
+                    map.print_map();
+                    print_legend();
+                    for (c, (x, y)) in map.feature_positions() {
+                        println!("{} - {} at ({},{})", c, map::symbol_name(c), x, y);
                     }
-                    //println!("{:?}", mappath.display());
-                    let e = mappath.extension().unwrap();                                  
-                    if e == "BIN" {
-                        let stem = mappath.file_stem().unwrap();
-                        let n: OsString = stem.to_os_string();
-                        //println!("{:?}", n);
-                        let newmap = map::load_map(&n).unwrap();
-                        let (first_half, last_half) = newmap.calculate_shortest_distance();                        
-                        let shortest_distance = first_half + last_half;
-                        path_lengths.entry(shortest_distance)
-                          .and_modify(|e: &mut Vec<OsString>| { e.push(n.clone()) })
-                          .or_insert(vec![n.clone()]);
-                    }                
+                },
+                Err(e) => println!("Couldn't load the dump: {}", e),
+            }
+        }
+        else if choice == 10 {
+            println!("Enter the path to the dump file");
+            let mut line2 = String::new();
+            let _count = std::io::stdin().read_line(&mut line2).unwrap();
+            println!("Enter the seed string it was made with (10 characters)");
+            let mut line3 = String::new();
+            let _count = std::io::stdin().read_line(&mut line3).unwrap();
+            let str_line = line3.as_str().strip_suffix("\n").unwrap();
+            let result = map::load_mednafen_file(line2.trim_end())
+                .and_then(|dump| map::verify_dump(&dump, &fcargs!(str_line, difficulty)));
+            match result {
+                Ok(None) => println!("The dump matches the generated map."),
+                Ok(Some(m)) => {
+                    println!("First mismatch at ({},{})", m.pos.0, m.pos.1);
+                    println!("Dump has      id {:#x} rotation {} height {}", m.dumped.id, m.dumped.rotation, m.dumped.height);
+                    println!("Generated has id {:#x} rotation {} height {}", m.generated.id, m.generated.rotation, m.generated.height);
+                    println!("Last written by: {}", m.phase);
+                },
+                Err(e) => println!("Couldn't check the dump: {}", e),
+            }
+        }
+        else if choice == 11 {
+            println!("Enter the path to the dump file");
+            let mut line2 = String::new();
+            let _count = std::io::stdin().read_line(&mut line2).unwrap();
+            println!("Enter what seed number you want to start on (blank for 0)");
+            let mut line3 = String::new();
+            let _count = std::io::stdin().read_line(&mut line3).unwrap();
+            let start = line3.trim_end().parse::<u32>().unwrap_or(0);
+            println!("Enter how many seeds to search (blank for all of them)");
+            let mut line4 = String::new();
+            let _count = std::io::stdin().read_line(&mut line4).unwrap();
+            let count = line4.trim_end().parse::<u64>().unwrap_or(1 << 32);
+            let now = Instant::now();
+            let result = map::load_mednafen_file(line2.trim_end())
+                .and_then(|dump| search::find_dump_seeds(&dump, difficulty, start, count));
+            match result {
+                Ok(seeds) => {
+                    for seed in seeds.iter() {
+                        println!("{:#010x} {}", seed, random::VHRandom::from_seed(*seed).get_code());
+                    }
+                    println!("{} matching seeds found in {} seconds", seeds.len(), now.elapsed().as_secs_f64());
+                },
+                Err(e) => println!("Couldn't search: {}", e),
+            }
+        }
+        else if choice == 12 {
+            println!("Enter what seed number you want to start on");
+            let mut line2 = String::new();
+            let _count = std::io::stdin().read_line(&mut line2).unwrap();
+            let start = line2.trim_end().parse::<u32>().unwrap();
+            println!("Enter the number of seeds to index ({} bytes each)", results::RESULT_SIZE);
+            let mut line3 = String::new();
+            let _count = std::io::stdin().read_line(&mut line3).unwrap();
+            let count = line3.trim_end().parse::<u64>().unwrap();
+            println!("Enter the file to save the index to");
+            let mut line4 = String::new();
+            let _count = std::io::stdin().read_line(&mut line4).unwrap();
+            let now = Instant::now();
+            // The index is a results database, written as it's swept rather than held in memory
+            let result = sweep::SeedRange::new(start, count).and_then(|range| {
+                let config = sweep::SweepConfig { range, difficulty, filter: None, save_dir: None, top: None, database: Some(line4.trim_end().to_string()) };
+                sweep::run_sweep(&config, sweep::default_threads(), None, None, true)
+            });
+            match result {
+                Ok(stats) => println!("Indexed {} seeds in {} seconds", stats.progress.done, now.elapsed().as_secs_f64()),
+                Err(e) => println!("Couldn't save the index: {}", e),
+            }
+        }
+        else if choice == 13 {
+            println!("Enter the index file");
+            let mut line2 = String::new();
+            let _count = std::io::stdin().read_line(&mut line2).unwrap();
+            println!("Enter what you know, separated by spaces. For example:");
+            println!("  base=4 rot=2      base map 4, rotated twice");
+            println!("  V=12,30~2         the volcano is at 12,30 give or take 2 tiles");
+            println!("  C-@=5,-3~1        the start is 5 east and 3 north of the castle tablet, give or take 1");
+            let mut line3 = String::new();
+            let _count = std::io::stdin().read_line(&mut line3).unwrap();
+            let result = results::ResultsDb::open(line2.trim_end())
+                .and_then(|db| Ok((db, index::Query::parse(line3.trim_end())?)))
+                .and_then(|(mut db, query)| {
+                    let mut shown = 0;
+                    let matched = db.scan(&query, |r| {
+                        if shown < 100 {
+                            println!("{:#010x} {}", r.seed, random::VHRandom::from_seed(r.seed).get_code());
+                            shown += 1;
+                        }
+                        true
+                    })?;
+                    if matched > shown {
+                        println!("... and {} more", matched - shown);
+                    }
+                    println!("{} of {} {} seeds match", matched, db.count, map::difficulty_text(&db.difficulty));
+                    Ok(true)
+                });
+            if let Err(e) = result {
+                println!("Couldn't look that up: {}", e);
+            }
+        }
+        else if choice == 14 {
+            println!("Enter a seed string (10 characters), or the path to a .json map");
+            let mut line2 = String::new();
+            let _count = std::io::stdin().read_line(&mut line2).unwrap();
+            let source = line2.as_str().strip_suffix("\n").unwrap();
+            println!("Enter the file to write the dump to");
+            let mut line3 = String::new();
+            let _count = std::io::stdin().read_line(&mut line3).unwrap();
+            let result = if source.ends_with(".json") {
+                fs::read_to_string(source).map_err(|e| e.into()).and_then(|text| map::OverworldMap::from_json(&text))
+            } else {
+                map::OverworldMap::from_code(&fcargs!(source, difficulty))
+            };
+            match result.and_then(|m| m.save_mednafen_map(line3.trim_end())) {
+                Ok(_) => println!("Wrote {}", line3.trim_end()),
+                Err(e) => println!("Couldn't write the dump: {}", e),
+            }
+        }
+        else if choice == 15 {
+            println!("Enter the path to a directory of GR_BASE files or a disc image");
+            let mut line2 = String::new();
+            let _count = std::io::stdin().read_line(&mut line2).unwrap();
+            let path = line2.trim_end();
+            let result = if std::path::Path::new(path).is_dir() {
+                basemaps::set_source(basemaps::BaseMapSource::Directory(path.to_string()))
+            } else {
+                println!("Enter Y to copy the base maps into ./basemaps, anything else to read them from the image from now on");
+                let mut line3 = String::new();
+                let _count = std::io::stdin().read_line(&mut line3).unwrap();
+                if line3.trim_end() == "Y" {
+                    disc::extract_base_maps(path, "./basemaps")
+                        .and_then(|_| basemaps::set_source(basemaps::BaseMapSource::default()))
+                } else {
+                    basemaps::set_source(basemaps::BaseMapSource::DiscImage(path.to_string()))
+                }
+            };
+            match result {
+                Ok(_) => {
+                    println!("Found all 5 base maps.");
+                    warn_about_base_maps();
+                },
+                Err(e) => println!("Couldn't get the base maps from {}: {}", path, e),
+            }
+        }
+        else if choice == 16 {
+            for (n, status) in basemaps::check_base_maps() {
+                println!("{}", status.describe(n));
+            }
+        }
+        else if choice == 17 {
+            println!("Enter the directory to save maps to, currently {}", output_dir);
+            let mut line2 = String::new();
+            let _count = std::io::stdin().read_line(&mut line2).unwrap();
+            let dir = line2.trim_end();
+            match fs::create_dir_all(dir) {
+                Ok(_) => output_dir = dir.to_string(),
+                Err(e) => println!("Couldn't use {}: {}", dir, e),
+            }
+        }
+        else if choice == 18 {
+            println!("Enter the path to a directory with your GR_BASE1.BIN to GR_BASE5.BIN");
+            let mut line2 = String::new();
+            let _count = std::io::stdin().read_line(&mut line2).unwrap();
+            let dir = line2.trim_end();
+            match basemaps::set_custom_source(basemaps::BaseMapSource::Directory(dir.to_string())) {
+                Ok(_) => println!("Generating on the base maps in {} from now on. Option 15 goes back to the retail ones.", dir),
+                Err(e) => println!("Can't use the base maps in {}: {}", dir, e),
+            }
+        }
+        else if choice == 20 {
+            println!("Enter the results database file");
+            let mut line2 = String::new();
+            let _count = std::io::stdin().read_line(&mut line2).unwrap();
+            println!("Enter a seed number or code to look up, or what you're after, like option 13 plus the route:");
+            println!("  last=9            the last half of the route (V-S-C) is 9");
+            println!("  total<=45         the whole route is at most 45, first= for the first half");
+            println!("  R-@=0,0~10        the start is within 10 tiles of the ruins");
+            let mut line3 = String::new();
+            let _count = std::io::stdin().read_line(&mut line3).unwrap();
+            let wanted = line3.trim_end_matches(['\r', '\n']);
+            let result = results::ResultsDb::open(line2.trim()).and_then(|mut db| {
+                println!("{}", results::summary(&db));
+                if let Some(seed) = results::parse_seed(wanted) {
+                    match db.get(seed)? {
+                        Some(r) => println!("{}", results::describe(&r)),
+                        None => println!("{:#010x} isn't in this database", seed),
+                    }
+                    return Ok(true);
+                }
+                let query = index::Query::parse(wanted)?;
+                // Like option 13, only the first 100 are shown but all of them are counted
+                let mut shown = 0;
+                let matched = db.scan(&query, |r| {
+                    if shown < 100 {
+                        println!("{}", results::describe(r));
+                        shown += 1;
+                    }
+                    true
+                })?;
+                if matched > shown {
+                    println!("... and {} more", matched - shown);
                 }
+                println!("{} of {} {} seeds match", matched, db.count, map::difficulty_text(&db.difficulty));
+                Ok(true)
+            });
+            if let Err(e) = result {
+                println!("Couldn't look that up: {}", e);
             }
-            let mut lengths: Vec<_> = path_lengths.keys().collect();
-            lengths.sort();
-            lengths.reverse();
-            for l in lengths {
-                println!("{}", l);
-                println!("{:?}", path_lengths[l]);
-            }        
+        }
+        else if choice == 19 {
+            println!("Enter the checkpoint file");
+            let mut line2 = String::new();
+            let _count = std::io::stdin().read_line(&mut line2).unwrap();
+            let path = line2.trim();
+            let has_records = checkpoint::Checkpoint::load(path).is_ok_and(|c| c.records.is_some());
+            print_sweep_result(sweep::resume_sweep(path, sweep::default_threads(), true), has_records, path, &output_dir);
         }
         else {
             println!("You didn't pick one of the options, so we're done! Congratulations.");
@@ -0,0 +1,172 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use crate::checkpoint::{Checkpoint, RecordsState};
+use crate::filter::Filter;
+use crate::leaderboard::Leaderboard;
+use crate::results::{ResultsDb, ResultsWriter};
+use crate::sweep::{RecordFormat, SeedRange, SweepConfig, SweepProgress};
+
+/// Checks that the named ranges cover one unbroken range of seeds exactly once, and returns
+/// that range. Every gap and overlap is listed in the error, not just the first.
+pub fn check_coverage(ranges: &[(String, SeedRange)]) -> Result<SeedRange, Box<dyn Error>> {
+    if ranges.is_empty() {
+        return Err("There's nothing to merge".into());
+    }
+    // The whole thing starts at a range nothing else leads into. With gaps there can be more
+    // than one, and with the whole seed space covered there are none.
+    let ends: HashSet<u32> = ranges.iter().filter(|(_, r)| r.count > 0).map(|(_, r)| r.seed(r.count)).collect();
+    let anchor = ranges.iter().map(|(_, r)| r.start).filter(|s| !ends.contains(s)).min()
+        .unwrap_or_else(|| ranges.iter().map(|(_, r)| r.start).min().unwrap_or(0));
+    let mut sorted: Vec<&(String, SeedRange)> = ranges.iter().collect();
+    sorted.sort_by_key(|(_, r)| (r.start.wrapping_sub(anchor), r.count));
+
+    let mut problems = Vec::new();
+    let mut covered = 0;
+    let mut previous = "";
+    for (name, range) in sorted {
+        let offset = range.start.wrapping_sub(anchor) as u64;
+        if offset > covered {
+            problems.push(format!("Seeds {:#010x} to {:#010x} aren't in any of them", anchor.wrapping_add(covered as u32), range.start.wrapping_sub(1)));
+        } else if offset < covered && range.count > 0 {
+            problems.push(format!("{} overlaps {} from seed {:#010x}", name, previous, range.start));
+        }
+        if offset + range.count > covered {
+            covered = offset + range.count;
+            previous = name;
+        }
+    }
+    if covered > 1 << 32 {
+        problems.push(format!("{} wraps round onto the start of the first one", previous));
+    }
+    match problems.is_empty() {
+        true => Ok(SeedRange { start: anchor, count: covered }),
+        false => Err(problems.join("\n").into()),
+    }
+}
+
+/// Combines the checkpoints written by the shards of a split sweep into one, as if the whole
+/// range had been swept in one go: the counts are added up, the leaderboards combined, and if
+/// asked for, the records files and results databases joined together in seed order. Each
+/// shard has to have finished, and between them they have to cover one range without gaps or
+/// overlaps.
+pub fn merge_checkpoints(paths: &[String], records_out: Option<&str>, database_out: Option<&str>) -> Result<Checkpoint, Box<dyn Error>> {
+    let mut shards = Vec::new();
+    for path in paths {
+        shards.push((path.clone(), Checkpoint::load(path)?));
+    }
+    let Some((first_path, first)) = shards.first().map(|(path, shard)| (path.clone(), shard.config.clone())) else {
+        return Err("There's nothing to merge".into());
+    };
+    let mut problems = Vec::new();
+    for (path, shard) in shards.iter() {
+        let config = &shard.config;
+        if config.difficulty != first.difficulty || config.filter != first.filter || config.top != first.top {
+            problems.push(format!("{} was swept with different settings (difficulty, filter or leaderboard) to {}", path, first_path));
+        }
+        if shard.progress.done < config.range.count {
+            problems.push(format!("{} has only done {} of its {} seeds, carry on with --resume {}", path, shard.progress.done, config.range.count, path));
+        }
+    }
+    let covered: Vec<(String, SeedRange)> = shards.iter()
+        .map(|(path, shard)| (path.clone(), SeedRange { start: shard.config.range.start, count: shard.progress.done }))
+        .collect();
+    let range = match check_coverage(&covered) {
+        Ok(range) => Some(range),
+        Err(e) => { problems.push(e.to_string()); None },
+    };
+    let Some(range) = range.filter(|_| problems.is_empty()) else {
+        return Err(problems.join("\n").into());
+    };
+    shards.sort_by_key(|(_, shard)| shard.config.range.start.wrapping_sub(range.start));
+
+    let size = first.top.map_or(0, |(_, size)| size);
+    let rejected = vec![0; first.filter.as_ref().map_or(0, |f| f.parts().len())];
+    let mut progress = SweepProgress { done: range.count, rejected, leaderboard: Leaderboard::new(size), ..Default::default() };
+    for (_, shard) in shards.iter() {
+        let p = &shard.progress;
+        progress.kept += p.kept;
+        progress.failed += p.failed;
+        for (total, r) in progress.rejected.iter_mut().zip(&p.rejected) {
+            *total += r;
+        }
+        // Ties go to the earlier seed, same as in one sweep
+        if let Some((seed, length)) = p.best {
+            if progress.best.is_none_or(|(_, best)| length < best) {
+                progress.best = Some((seed, length));
+            }
+        }
+        for entry in p.leaderboard.entries() {
+            progress.leaderboard.offer(entry);
+        }
+    }
+
+    let records = match records_out {
+        Some(path) => Some(join_records(&shards, path)?),
+        None => None,
+    };
+    if let Some(path) = database_out {
+        let mut out = ResultsWriter::create(path, range.start, range.count, first.difficulty)?;
+        for (name, shard) in shards.iter() {
+            let db_path = shard.config.database.as_deref().ok_or(format!("{} didn't write a results database", name))?;
+            let mut db = ResultsDb::open(db_path)?;
+            if db.start != shard.config.range.start || db.count != shard.progress.done {
+                return Err(format!("{} doesn't match {}, it's for a different range", db_path, name).into());
+            }
+            out.append(&mut db)?;
+        }
+        out.flush()?;
+    }
+
+    let config = SweepConfig { range, save_dir: None, database: database_out.map(String::from), ..first };
+    Ok(Checkpoint { config, progress, records })
+}
+
+// Joins the shards' records files into one, in order, with only the first header kept
+fn join_records(shards: &[(String, Checkpoint)], path: &str) -> Result<RecordsState, Box<dyn Error>> {
+    let mut format = None;
+    let mut out = BufWriter::new(File::create(path)?);
+    for (i, (name, shard)) in shards.iter().enumerate() {
+        let state = shard.records.as_ref().ok_or(format!("{} didn't write any records", name))?;
+        let shard_path = state.path.as_deref().ok_or(format!("{} wrote its records to the screen, there's no file to merge", name))?;
+        if format.is_some_and(|f| f != state.format) {
+            return Err(format!("{} wrote {} records, not the same as the other shards", name, state.format.name()).into());
+        }
+        format = Some(state.format);
+        // Only what was there at the checkpoint counts
+        let mut reader = BufReader::new(File::open(shard_path)?.take(state.length));
+        if i > 0 && state.format.header().is_some() {
+            reader.read_line(&mut String::new())?;
+        }
+        std::io::copy(&mut reader, &mut out)?;
+    }
+    out.flush()?;
+    drop(out);
+    Ok(RecordsState { format: format.unwrap_or(RecordFormat::Csv), path: Some(path.to_string()), length: std::fs::metadata(path)?.len() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(list: &[(u32, u64)]) -> Vec<(String, SeedRange)> {
+        list.iter().enumerate().map(|(i, &(start, count))| (format!("shard{}", i + 1), SeedRange { start, count })).collect()
+    }
+
+    #[test]
+    fn checks_coverage() {
+        assert!(check_coverage(&ranges(&[(100, 50), (0, 100), (150, 10)])).unwrap() == SeedRange { start: 0, count: 160 });
+        // Wrapping past u32::MAX, and the whole seed space
+        assert!(check_coverage(&ranges(&[(5, 10), (u32::MAX - 4, 10)])).unwrap() == SeedRange { start: u32::MAX - 4, count: 20 });
+        let whole = SeedRange::new(7, 1 << 32).unwrap();
+        let shards: Vec<(String, SeedRange)> = (1..=3).map(|k| (k.to_string(), whole.shard(k, 3).unwrap())).collect();
+        assert!(check_coverage(&shards).unwrap() == whole);
+
+        let gap = check_coverage(&ranges(&[(0, 100), (150, 10)])).unwrap_err().to_string();
+        assert!(gap == "Seeds 0x00000064 to 0x00000095 aren't in any of them");
+        let overlap = check_coverage(&ranges(&[(0, 100), (90, 10), (100, 5)])).unwrap_err().to_string();
+        assert!(overlap == "shard2 overlaps shard1 from seed 0x0000005a");
+        assert!(check_coverage(&ranges(&[(0, 1 << 32), (0, 1)])).is_err());
+    }
+}
@@ -30,6 +30,7 @@ impl VHRandom {
 
     /// Creates a new RNG from a valid string code. The code must be the full 10 characters,
     /// pad the code with spaces first for "shorter" codes.
+    #[allow(clippy::precedence)]
     pub fn from_code(code: &str) -> Option<Self> {
         let raw_code: Vec<u8> = code
             .chars()
@@ -65,9 +66,6 @@ impl VHRandom {
                 let letter_shifted: u32 = ((letter & letter_mask) as u32)
                     .wrapping_shl((32 - c1_shift).saturating_sub(shift));
 
-                #[cfg(debug_assertions)]
-                println!("{:#x} {} {} {:#x}", letter, c1_shift, shift, letter_shifted);
-
                 c1 |= letter_shifted;
                 c1_shift += shift;
 
@@ -82,19 +80,11 @@ impl VHRandom {
                 let letter_mask = (1 << shift) - 1;
                 let letter_shifted: u32 = ((letter & letter_mask) as u32)
                     .wrapping_shl((32 - c2_shift).saturating_sub(shift));
-                #[cfg(debug_assertions)]
-                println!("{:#x} {} {} {:#x}", letter, c2_shift, shift, letter_shifted);
-
                 c2 |= letter_shifted;
                 c2_shift += shift;
             }
-            #[cfg(debug_assertions)]
-            println!("c1:{:#x} c2:{:#x}", c1, c2);
         }
         
-        #[cfg(debug_assertions)]
-        println!("Seed gen: {:#x} {:#x} {} {:#x}", c1, c2, c2_shift, c1 ^ (c2 >> 32 - c2_shift));
-
         Some(VHRandom {
             seed: c1 ^ (c2 >> 32 - c2_shift),
             code: code_array,
@@ -180,6 +170,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::mixed_case_hex_literals, clippy::needless_range_loop)]
     fn known_seed_sequence() {
         let mut rng = VHRandom::from_code("CCCCCCCCBB").unwrap();
         assert!(rng.get_seed() == 0x11111111);
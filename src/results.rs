@@ -0,0 +1,309 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use crate::index::{self, Query, SeedRecord, INDEX_FEATURES};
+use crate::map::{self, Difficulty};
+use crate::random::VHRandom;
+
+const RESULTS_MAGIC: &[u8; 4] = b"VHRS";
+const RESULTS_VERSION: u8 = 1;
+const HEADER_SIZE: u64 = 16;
+/// Bytes per seed: one for the base map and rotation, then 12 bits for each half of the route
+/// and 6 bits for each coordinate of each of INDEX_FEATURES
+pub const RESULT_SIZE: usize = (8 + 2 * ROUTE_BITS + INDEX_FEATURES.len() * 2 * COORD_BITS) / 8;
+const ROUTE_BITS: usize = 12;
+const COORD_BITS: usize = 6;
+// Written for features that aren't on the map and routes that weren't worked out
+const NO_ROUTE: u64 = (1 << ROUTE_BITS) - 1;
+const NO_COORD: u64 = (1 << COORD_BITS) - 1;
+// How many records a scan reads at a time
+const SCAN_BATCH: usize = 1 << 16;
+
+// Fixed width fields packed most significant bit first, starting after the first byte
+fn put_bits(bytes: &mut [u8], at: usize, width: usize, value: u64) {
+    for i in 0..width {
+        let bit = at + i;
+        if value >> (width - 1 - i) & 1 == 1 {
+            bytes[bit / 8] |= 0x80 >> (bit % 8);
+        }
+    }
+}
+
+fn get_bits(bytes: &[u8], at: usize, width: usize) -> u64 {
+    (0..width).fold(0, |value, i| {
+        let bit = at + i;
+        value << 1 | (bytes[bit / 8] >> (7 - bit % 8) & 1) as u64
+    })
+}
+
+/// Packs a record into RESULT_SIZE bytes. Base map 0 (a seed that didn't generate) packs to a
+/// zero first byte.
+pub fn pack(r: &SeedRecord) -> [u8; RESULT_SIZE] {
+    let mut bytes = [0; RESULT_SIZE];
+    bytes[0] = r.base_map << 2 | r.base_rotation & 3;
+    let (first, last) = r.route.map_or((NO_ROUTE, NO_ROUTE), |(f, l)| (f.min(NO_ROUTE as u16 - 1) as u64, l.min(NO_ROUTE as u16 - 1) as u64));
+    put_bits(&mut bytes, 8, ROUTE_BITS, first);
+    put_bits(&mut bytes, 8 + ROUTE_BITS, ROUTE_BITS, last);
+    for (i, p) in r.positions.iter().enumerate() {
+        let (x, y) = p.map_or((NO_COORD, NO_COORD), |(x, y)| (x as u64, y as u64));
+        let at = 8 + 2 * ROUTE_BITS + i * 2 * COORD_BITS;
+        put_bits(&mut bytes, at, COORD_BITS, x);
+        put_bits(&mut bytes, at + COORD_BITS, COORD_BITS, y);
+    }
+    bytes
+}
+
+pub fn unpack(seed: u32, bytes: &[u8]) -> SeedRecord {
+    let mut r = SeedRecord::empty(seed);
+    r.base_map = bytes[0] >> 2;
+    r.base_rotation = bytes[0] & 3;
+    let (first, last) = (get_bits(bytes, 8, ROUTE_BITS), get_bits(bytes, 8 + ROUTE_BITS, ROUTE_BITS));
+    if first != NO_ROUTE {
+        r.route = Some((first as u16, last as u16));
+    }
+    for (i, p) in r.positions.iter_mut().enumerate() {
+        let at = 8 + 2 * ROUTE_BITS + i * 2 * COORD_BITS;
+        let (x, y) = (get_bits(bytes, at, COORD_BITS), get_bits(bytes, at + COORD_BITS, COORD_BITS));
+        if x != NO_COORD {
+            *p = Some((x as u8, y as u8));
+        }
+    }
+    r
+}
+
+// A 16 byte header: magic, version, difficulty, two spare bytes, then the first seed and the
+// record count less one (so all 2^32 fit) as big endian u32s
+fn header(start: u32, count: u64, difficulty: Difficulty) -> Vec<u8> {
+    let mut out = Vec::with_capacity(HEADER_SIZE as usize);
+    out.extend_from_slice(RESULTS_MAGIC);
+    out.push(RESULTS_VERSION);
+    out.push(index::difficulty_byte(difficulty));
+    out.extend_from_slice(&[0, 0]);
+    out.extend_from_slice(&start.to_be_bytes());
+    out.extend_from_slice(&(count as u32).wrapping_sub(1).to_be_bytes());
+    out
+}
+
+/// Writes a results database one seed at a time, in seed order, as a sweep finds them
+pub struct ResultsWriter {
+    out: BufWriter<File>,
+}
+
+impl ResultsWriter {
+    /// Starts a database for `count` seeds from `start`
+    pub fn create(path: &str, start: u32, count: u64, difficulty: Difficulty) -> Result<ResultsWriter, Box<dyn Error>> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(&header(start, count, difficulty))?;
+        Ok(ResultsWriter { out })
+    }
+
+    /// Picks a database back up after its first `done` seeds, cutting off anything written
+    /// after them, for resuming a sweep
+    pub fn reopen(path: &str, done: u64) -> Result<ResultsWriter, Box<dyn Error>> {
+        let mut file = std::fs::OpenOptions::new().write(true).open(path)?;
+        let length = HEADER_SIZE + done * RESULT_SIZE as u64;
+        if file.metadata()?.len() < length {
+            return Err(format!("{} is shorter than when the checkpoint was written", path).into());
+        }
+        file.set_len(length)?;
+        file.seek(SeekFrom::End(0))?;
+        Ok(ResultsWriter { out: BufWriter::new(file) })
+    }
+
+    pub fn write(&mut self, r: &SeedRecord) -> Result<bool, Box<dyn Error>> {
+        self.out.write_all(&pack(r))?;
+        Ok(true)
+    }
+
+    pub fn flush(&mut self) -> Result<bool, Box<dyn Error>> {
+        self.out.flush()?;
+        Ok(true)
+    }
+
+    /// Copies every record from another database onto the end of this one as they are, for
+    /// merging shards
+    pub fn append(&mut self, db: &mut ResultsDb) -> Result<bool, Box<dyn Error>> {
+        db.file.seek(SeekFrom::Start(HEADER_SIZE))?;
+        let length = db.count * RESULT_SIZE as u64;
+        if std::io::copy(&mut (&mut db.file).take(length), &mut self.out)? != length {
+            return Err("A results database got shorter while it was being copied".into());
+        }
+        Ok(true)
+    }
+}
+
+/// A results database on disk: one fixed size record per seed for a consecutive range, so any
+/// seed can be read straight off the disk and a whole file scanned without generating anything.
+/// Nothing is loaded up front, the whole seed space is about 90GB.
+pub struct ResultsDb {
+    file: File,
+    pub start: u32,
+    pub difficulty: Difficulty,
+    /// How many seeds the sweep that wrote it was meant to cover
+    pub planned: u64,
+    /// How many it actually has, fewer if the sweep was stopped
+    pub count: u64,
+}
+
+impl ResultsDb {
+    pub fn open(path: &str) -> Result<ResultsDb, Box<dyn Error>> {
+        let mut file = File::open(path).map_err(|e| format!("Can't open {}: {}", path, e))?;
+        let mut header = [0; HEADER_SIZE as usize];
+        if file.read_exact(&mut header).is_err() || &header[0..4] != RESULTS_MAGIC {
+            return Err(format!("{} isn't a results database", path).into());
+        }
+        if header[4] != RESULTS_VERSION {
+            return Err(format!("Results database version {} isn't supported", header[4]).into());
+        }
+        let difficulty = index::byte_difficulty(header[5]);
+        let start = u32::from_be_bytes(header[8..12].try_into()?);
+        let planned = u32::from_be_bytes(header[12..16].try_into()?) as u64 + 1;
+        let count = ((file.metadata()?.len() - HEADER_SIZE) / RESULT_SIZE as u64).min(planned);
+        Ok(ResultsDb { file, start, difficulty, planned, count })
+    }
+
+    /// The record for a seed, if it's in the database
+    pub fn get(&mut self, seed: u32) -> Result<Option<SeedRecord>, Box<dyn Error>> {
+        let i = seed.wrapping_sub(self.start) as u64;
+        if i >= self.count {
+            return Ok(None);
+        }
+        let mut bytes = [0; RESULT_SIZE];
+        self.file.seek(SeekFrom::Start(HEADER_SIZE + i * RESULT_SIZE as u64))?;
+        self.file.read_exact(&mut bytes)?;
+        Ok(Some(unpack(seed, &bytes)))
+    }
+
+    /// Reads through the whole database in order, handing every record that matches the query
+    /// to `found`, which can return false to stop early. Returns how many matched.
+    pub fn scan(&mut self, query: &Query, mut found: impl FnMut(&SeedRecord) -> bool) -> Result<u64, Box<dyn Error>> {
+        self.file.seek(SeekFrom::Start(HEADER_SIZE))?;
+        let mut buffer = vec![0; SCAN_BATCH * RESULT_SIZE];
+        let mut matched = 0;
+        let mut i = 0;
+        while i < self.count {
+            let batch = (self.count - i).min(SCAN_BATCH as u64) as usize;
+            self.file.read_exact(&mut buffer[..batch * RESULT_SIZE])?;
+            for (j, bytes) in buffer[..batch * RESULT_SIZE].chunks(RESULT_SIZE).enumerate() {
+                // Seeds that didn't generate can't match anything
+                if bytes[0] == 0 {
+                    continue;
+                }
+                let r = unpack(self.start.wrapping_add((i + j as u64) as u32), bytes);
+                if query.matches(&r) {
+                    matched += 1;
+                    if !found(&r) {
+                        return Ok(matched);
+                    }
+                }
+            }
+            i += batch as u64;
+        }
+        Ok(matched)
+    }
+}
+
+/// A seed given as a number (decimal or 0x hex) or a 10 character code. None if it's neither,
+/// which lets a query be told apart from a lookup.
+pub fn parse_seed(s: &str) -> Option<u32> {
+    if let Some(hex) = s.strip_prefix("0x") {
+        return u32::from_str_radix(hex, 16).ok();
+    }
+    if let Ok(seed) = s.parse() {
+        return Some(seed);
+    }
+    if s.contains('=') {
+        return None;
+    }
+    VHRandom::from_code(s).map(|rng| rng.get_seed())
+}
+
+/// One line for a record: seed, code, base map and rotation, route and where everything is
+pub fn describe(r: &SeedRecord) -> String {
+    let mut out = format!("{:#010x} {}", r.seed, VHRandom::from_seed(r.seed).get_code());
+    if r.base_map == 0 {
+        return out + " didn't generate";
+    }
+    out += &format!(" base {} rot {}", r.base_map, r.base_rotation);
+    if let Some((first, last)) = r.route {
+        out += &format!(" route {}+{}={}", first, last, first + last);
+    }
+    for (c, p) in INDEX_FEATURES.chars().zip(r.positions.iter()) {
+        if let Some((x, y)) = p {
+            out += &format!(" {}{},{}", c, x, y);
+        }
+    }
+    out
+}
+
+/// The first line `db <file>` prints
+pub fn summary(db: &ResultsDb) -> String {
+    let last = db.start.wrapping_add(db.count.saturating_sub(1) as u32);
+    let mut out = format!("{} seeds from {:#010x} to {:#010x} at {}", db.count, db.start, last, map::difficulty_text(&db.difficulty));
+    if db.count < db.planned {
+        out += &format!(", {} more to go", db.planned - db.count);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(seed: u32) -> SeedRecord {
+        let mut r = SeedRecord::empty(seed);
+        r.base_map = 1 + (seed % 5) as u8;
+        r.base_rotation = (seed % 4) as u8;
+        r.route = Some((30 + (seed % 7) as u16, 9 + (seed % 3) as u16));
+        r.positions[0] = Some((49, seed as u8 % 50));
+        r.positions[6] = Some((12, 30));
+        r
+    }
+
+    #[test]
+    fn packs_records() {
+        assert!(pack(&record(0)).len() == 22);
+        for seed in [0, 1, 2, 77] {
+            assert!(unpack(seed, &pack(&record(seed))) == record(seed));
+        }
+        assert!(unpack(5, &pack(&SeedRecord::empty(5))) == SeedRecord::empty(5));
+        assert!(parse_seed("0x10") == Some(16) && parse_seed("77") == Some(77) && parse_seed("last=9").is_none());
+        let code = VHRandom::from_seed(1234).get_code();
+        assert!(parse_seed(&code) == Some(1234));
+    }
+
+    #[test]
+    fn writes_reads_and_scans() {
+        let path = std::env::temp_dir().join(format!("vh_results_{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let start = u32::MAX - 9;
+        let mut writer = ResultsWriter::create(path, start, 30, Difficulty::Hard).unwrap();
+        for i in 0..20 {
+            let seed = start.wrapping_add(i);
+            writer.write(&if i == 3 { SeedRecord::empty(seed) } else { record(seed) }).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+        // A resume from 15 done drops the last five and carries on
+        let mut writer = ResultsWriter::reopen(path, 15).unwrap();
+        for i in 15..30 {
+            writer.write(&record(start.wrapping_add(i))).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+
+        let mut db = ResultsDb::open(path).unwrap();
+        assert!(db.count == 30 && db.planned == 30 && db.difficulty == Difficulty::Hard);
+        assert!(db.get(5).unwrap() == Some(record(5)));
+        assert!(db.get(start.wrapping_add(3)).unwrap() == Some(SeedRecord::empty(start.wrapping_add(3))));
+        assert!(db.get(20).unwrap().is_none() && db.get(start - 1).unwrap().is_none());
+
+        let mut seeds = Vec::new();
+        let query = Query::parse("last=9 V=12,30").unwrap();
+        let matched = db.scan(&query, |r| { seeds.push(r.seed); true }).unwrap();
+        let expected: Vec<u32> = (0..30).map(|i| start.wrapping_add(i)).filter(|&s| s % 3 == 0 && s != start.wrapping_add(3)).collect();
+        assert!(matched == expected.len() as u64 && seeds == expected);
+        assert!(db.scan(&query, |_| false).unwrap() == 1);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+use std::error::Error;
+use crate::map::{self, _FCargs, Difficulty, OverworldMap};
+use crate::random::VHRandom;
+use crate::fcargs;
+use crate::sweep::{default_threads, sweep_seeds, SeedRange};
+
+/// The base map id (1-5) and base rotation a seed will use. These are the first two rolls
+/// from_code makes, so they can be checked without generating anything.
+pub fn first_rolls(seed: u32) -> (u32, u8) {
+    let mut rng = VHRandom::from_seed(seed);
+    let map_id = rng.rand(5) + 1;
+    let rotation = rng.rand(4) as u8;
+    (map_id, rotation)
+}
+
+/// A (base map id, rotation) pair and the base map rotated that way
+pub type BaseVariant = ((u32, u8), OverworldMap);
+
+/// All 20 variants a map can be built on
+pub fn base_variants() -> Result<Vec<BaseVariant>, Box<dyn Error>> {
+    Ok(crate::basemaps::base_maps()?.variants().map(|(variant, base)| (variant, base.clone())).collect())
+}
+
+/// How much of each base map variant is still visible in `map`: the fraction of the base's
+/// filled in (non-zero) tiles that `map` has unchanged. Generation only fills the empty tiles and
+/// drops features on top, so the variant a map came from scores close to 1 and the rest don't.
+pub fn base_variant_scores(map: &OverworldMap, variants: &[BaseVariant]) -> Vec<((u32, u8), f64)> {
+    let mut scores: Vec<((u32, u8), f64)> = variants.iter().map(|(variant, base)| {
+        let fixed: Vec<_> = base.tiles().iter().zip(map.tiles().iter()).filter(|(b, _)| b.id != 0).collect();
+        let kept = fixed.iter().filter(|(b, m)| b == m).count();
+        (*variant, if fixed.is_empty() { 0.0 } else { kept as f64 / fixed.len() as f64 })
+    }).collect();
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+    scores
+}
+
+/// Searches `count` seeds from `start` for the ones that generate exactly `dump` (a map loaded with
+/// load_mednafen_map) at the given difficulty. It's one pass over the seeds, generating every one
+/// whatever variant its first two rolls pick, and if more than one variant has matches the one
+/// most like the dump wins. Returns the matching seeds in order.
+pub fn find_dump_seeds(dump: &OverworldMap, difficulty: Difficulty, start: u32, count: u64) -> Result<Vec<u32>, Box<dyn Error>> {
+    let ranked: Vec<(u32, u8)> = base_variant_scores(dump, &base_variants()?).into_iter().map(|(variant, _)| variant).collect();
+    let end = (start as u64 + count).min(1 << 32);
+    Ok(find_in_variants(&ranked, start as u64, end, |seed| {
+        let code = VHRandom::from_seed(seed).get_code();
+        let generated = OverworldMap::from_code(&fcargs!(code, difficulty));
+        generated.is_ok_and(|generated| map::first_dump_mismatch(dump, &generated).is_none())
+    }))
+}
+
+// One pass over start..end: each seed's first rolls pick its variant, seeds on a ranked variant
+// get checked, and the matches are bucketed by variant. The first variant in ranked order with
+// any matches wins. Generation only drops features on top of a base map's filled in tiles, so a
+// dump can't also come from a variant it doesn't look like.
+fn find_in_variants(ranked: &[(u32, u8)], start: u64, end: u64, matches: impl Fn(u32) -> bool + Sync) -> Vec<u32> {
+    let found = filter_map_seeds(start, end, |seed| {
+        let rank = ranked.iter().position(|v| *v == first_rolls(seed))?;
+        matches(seed).then_some((rank, seed))
+    });
+    let mut buckets = vec![Vec::new(); ranked.len()];
+    for (rank, seed) in found {
+        buckets[rank].push(seed);
+    }
+    buckets.into_iter().find(|bucket| !bucket.is_empty()).unwrap_or_default()
+}
+
+/// Runs `f` on every seed in start..end, split across all the cores, and collects whatever it
+/// returns in seed order. end can be 2^32 to include the last seed.
+pub fn filter_map_seeds<T: Send>(start: u64, end: u64, f: impl Fn(u32) -> Option<T> + Sync) -> Vec<T> {
+    let mut found = Vec::new();
+    let range = SeedRange { start: start as u32, count: end.saturating_sub(start) };
+    sweep_seeds(range, default_threads(), f, |_, t| { found.push(t); Ok(true) }, |_| Ok(true)).unwrap();
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::Tile;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn first_rolls_match_rng() {
+        for seed in [0, 1, 0x11111111, 0xfffc0000] {
+            let mut rng = VHRandom::from_seed(seed);
+            let expected = (rng.rand(5) + 1, rng.rand(4) as u8);
+            assert!(first_rolls(seed) == expected);
+            assert!((1..6).contains(&expected.0) && expected.1 < 4);
+        }
+    }
+
+    #[test]
+    fn filter_map_seeds_is_ordered() {
+        let odd = filter_map_seeds(10, 31, |seed| (seed % 2 == 1).then_some(seed));
+        assert!(odd == (11..31).step_by(2).collect::<Vec<u32>>());
+        let last = filter_map_seeds(u32::MAX as u64 - 1, 1 << 32, Some);
+        assert!(last == vec![u32::MAX - 1, u32::MAX]);
+        assert!(filter_map_seeds(5, 5, Some).is_empty());
+    }
+
+    #[test]
+    fn searches_every_variant_in_order() {
+        let seeds: Vec<u32> = (0..200).collect();
+        let variant_of = |seed: u32| first_rolls(seed);
+        let (best, worst) = (variant_of(3), variant_of(7));
+        let mut ranked: Vec<(u32, u8)> = seeds.iter().map(|s| variant_of(*s)).collect();
+        ranked.sort();
+        ranked.dedup();
+        ranked.retain(|v| *v != best && *v != worst);
+        ranked.insert(0, best);
+        ranked.push(worst);
+
+        // Only the worst looking variant has a match, and it still gets found
+        assert!(find_in_variants(&ranked, 0, 200, |seed| seed == 7) == vec![7]);
+        // When more than one variant has matches only the best ranked one's come back
+        let both = find_in_variants(&ranked, 0, 200, |seed| seed == 3 || seed == 7);
+        assert!(both == vec![3]);
+        // Every seed gets looked at once, not once per variant
+        let checked = AtomicUsize::new(0);
+        assert!(find_in_variants(&ranked, 0, 200, |_| { checked.fetch_add(1, Ordering::SeqCst); false }).is_empty());
+        assert!(checked.load(Ordering::SeqCst) == 200);
+    }
+
+    #[test]
+    fn picks_the_base_a_map_was_built_on() {
+        let empty = Tile { id: 0, rotation: 0, height: 0 };
+        let mut a = vec![empty.clone(); 50*50];
+        let mut b = vec![empty.clone(); 50*50];
+        for i in 0..100 {
+            a[i].id = 0x1b;
+            b[2400 + i].id = 0x1b;
+        }
+        let variants = vec![((1, 0), OverworldMap::from_tiles(50, 50, a.clone()).unwrap()),
+                            ((2, 3), OverworldMap::from_tiles(50, 50, b).unwrap())];
+        // Fill in the rest and drop a feature on one of the base tiles
+        let mut generated: Vec<Tile> = a.iter().map(|t| if t.id == 0 { Tile { id: 9, ..t.clone() } } else { t.clone() }).collect();
+        generated[3].id = 0x2d;
+        let generated = OverworldMap::from_tiles(50, 50, generated).unwrap();
+
+        let scores = base_variant_scores(&generated, &variants);
+        assert!(scores[0] == ((1, 0), 0.99));
+        assert!(scores[1] == ((2, 3), 0.0));
+    }
+}
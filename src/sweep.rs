@@ -0,0 +1,684 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::{Seek, Write};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+use crate::checkpoint::{self, Checkpoint, RecordsState};
+use crate::filter::{Expression, Filter};
+use crate::index::{SeedRecord, INDEX_FEATURES};
+use crate::json::Value;
+use crate::leaderboard::{Entry, Leaderboard, Metric};
+use crate::results::ResultsWriter;
+use crate::map::{_FCargs, Difficulty, OverworldMap, Winnowed};
+use crate::random::VHRandom;
+use crate::fcargs;
+
+/// `count` seeds from `start`, wrapping around past u32::MAX to 0. count can be up to 2^32 for
+/// the whole seed space.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SeedRange {
+    pub start: u32,
+    pub count: u64,
+}
+
+impl SeedRange {
+    pub fn new(start: u32, count: u64) -> Result<SeedRange, Box<dyn Error>> {
+        if count > 1 << 32 {
+            return Err(format!("Can't sweep {} seeds, there are only 2^32", count).into());
+        }
+        Ok(SeedRange { start, count })
+    }
+
+    /// The ith seed of the range
+    pub fn seed(&self, i: u64) -> u32 {
+        (self.start as u64 + i) as u32
+    }
+
+    /// Shard `k` of `n` (counting from 1): the range cut into n consecutive pieces whose sizes
+    /// differ by at most one. The same k/n always gives the same seeds, so each machine in a
+    /// split sweep only needs to be told its k.
+    pub fn shard(&self, k: u64, n: u64) -> Result<SeedRange, Box<dyn Error>> {
+        if n == 0 || k == 0 || k > n {
+            return Err(format!("Shard {}/{} doesn't exist, it should be 1/n to n/n", k, n).into());
+        }
+        if n > self.count {
+            return Err(format!("Can't split {} seeds into {} shards", self.count, n).into());
+        }
+        let from = (k - 1) * self.count / n;
+        let to = k * self.count / n;
+        Ok(SeedRange { start: self.seed(from), count: to - from })
+    }
+}
+
+/// Seeds handed to a thread at a time
+pub const CHUNK_SIZE: u64 = 4096;
+
+// How far ahead of the oldest unfinished chunk threads may get, per thread. Finished chunks wait
+// in memory until everything before them is done, this keeps that bounded.
+const CHUNKS_AHEAD: u64 = 4;
+
+/// Every core the machine has
+pub fn default_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+struct Schedule {
+    next_chunk: u64,
+    emitted: u64,
+    stop: bool,
+}
+
+// If f panics, the chunk it was on never arrives and the other threads would wait forever for
+// it to be emitted. This stops them so the panic gets to the caller.
+struct StopOnPanic<'a>(&'a Mutex<Schedule>, &'a Condvar);
+
+impl Drop for StopOnPanic<'_> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            if let Ok(mut state) = self.0.lock() {
+                state.stop = true;
+            }
+            self.1.notify_all();
+        }
+    }
+}
+
+/// Runs `f` on every seed in `range` across `threads` threads and hands whatever it returns to
+/// `emit` in seed order, on the calling thread. The seeds are split into chunks that threads take
+/// from a shared counter as they finish their last one, so a slow stretch of seeds doesn't leave
+/// the other threads idle. The output is the same whatever the thread count.
+///
+/// After each chunk's results are emitted `done` is told how many seeds from the start of the
+/// range are finished, and can return false to stop there. Returns false if it was stopped that
+/// way. If emit or done fail the sweep stops and returns the error.
+pub fn sweep_seeds<T: Send>(
+    range: SeedRange,
+    threads: usize,
+    f: impl Fn(u32) -> Option<T> + Sync,
+    mut emit: impl FnMut(u32, T) -> Result<bool, Box<dyn Error>>,
+    mut done: impl FnMut(u64) -> Result<bool, Box<dyn Error>>,
+) -> Result<bool, Box<dyn Error>> {
+    let chunks = range.count.div_ceil(CHUNK_SIZE);
+    let threads = threads.max(1);
+    let window = threads as u64 * CHUNKS_AHEAD;
+    let schedule = Mutex::new(Schedule { next_chunk: 0, emitted: 0, stop: false });
+    let progress = Condvar::new();
+    let (sender, receiver) = mpsc::channel::<(u64, Vec<(u32, T)>)>();
+
+    thread::scope(|s| {
+        for _ in 0..threads {
+            let sender = sender.clone();
+            let (f, schedule, progress) = (&f, &schedule, &progress);
+            s.spawn(move || loop {
+                let _guard = StopOnPanic(schedule, progress);
+                let chunk = {
+                    let mut state = schedule.lock().unwrap();
+                    while !state.stop && state.next_chunk < chunks && state.next_chunk >= state.emitted + window {
+                        state = progress.wait(state).unwrap();
+                    }
+                    if state.stop || state.next_chunk >= chunks {
+                        return;
+                    }
+                    state.next_chunk += 1;
+                    state.next_chunk - 1
+                };
+                let from = chunk * CHUNK_SIZE;
+                let to = (from + CHUNK_SIZE).min(range.count);
+                let found = (from..to).filter_map(|i| {
+                    let seed = range.seed(i);
+                    f(seed).map(|t| (seed, t))
+                }).collect();
+                if sender.send((chunk, found)).is_err() {
+                    return;
+                }
+            });
+        }
+        drop(sender);
+
+        // Put the chunks back in order as they come in
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        for (chunk, found) in receiver.iter() {
+            pending.insert(chunk, found);
+            while let Some(found) = pending.remove(&next) {
+                let emitted = found.into_iter().try_for_each(|(seed, t)| emit(seed, t).map(|_| ()));
+                next += 1;
+                let carry_on = emitted.and_then(|_| done((next * CHUNK_SIZE).min(range.count)));
+                let mut state = schedule.lock().unwrap();
+                state.emitted = next;
+                state.stop = !matches!(carry_on, Ok(true));
+                progress.notify_all();
+                drop(state);
+                if !carry_on? {
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    })
+}
+
+/// What to sweep and what to do with the maps that are kept
+#[derive(Debug, PartialEq, Clone)]
+pub struct SweepConfig {
+    pub range: SeedRange,
+    pub difficulty: Difficulty,
+    /// Only keep the maps this lets through
+    pub filter: Option<Expression>,
+    /// Where to save kept maps as BIN, if anywhere
+    pub save_dir: Option<String>,
+    /// Keep a leaderboard of this many of the best kept maps by this metric
+    pub top: Option<(Metric, usize)>,
+    /// Write a results database of every seed here. Can't be used with a filter, since a
+    /// thrown away map isn't finished.
+    pub database: Option<String>,
+}
+
+/// How far through its range a sweep has got, which is what a checkpoint carries over
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct SweepProgress {
+    /// Seeds finished, counting from the start of the range. Results come out in seed order, so
+    /// these are always the first `done` seeds.
+    pub done: u64,
+    pub kept: u64,
+    /// The kept seed with the shortest route so far, and the route's length
+    pub best: Option<(u32, u16)>,
+    /// Seeds thrown away by each part of the filter. A seed is only counted against the part
+    /// that ruled it out first, which is the one checked earliest in generation.
+    pub rejected: Vec<u64>,
+    /// Seeds that couldn't be generated at all
+    pub failed: u64,
+    /// Empty unless the config asks for one
+    pub leaderboard: Leaderboard,
+}
+
+impl SweepProgress {
+    /// How many of the seeds done made it past a part of the filter. Seeds that failed to
+    /// generate never made it past any.
+    pub fn survivors(&self, part: usize) -> u64 {
+        self.done - self.failed - self.rejected.iter().take(part + 1).sum::<u64>()
+    }
+}
+
+/// What a sweep did
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct SweepStats {
+    /// Seeds generated this run, not counting any done before a resume
+    pub seeds: u64,
+    pub progress: SweepProgress,
+    pub seconds: f64,
+    /// False if it was stopped with Ctrl-C before the end of the range
+    pub finished: bool,
+}
+
+// How often a checkpoint is written while sweeping
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(30);
+// And how often progress is printed
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Durations the way a person reads them: "2d 04h", "3h 12m", "4m 05s", "12s"
+fn format_duration(seconds: f64) -> String {
+    let s = seconds.round() as u64;
+    match s {
+        0..60 => format!("{}s", s),
+        60..3600 => format!("{}m {:02}s", s / 60, s % 60),
+        3600..86400 => format!("{}h {:02}m", s / 3600, s % 3600 / 60),
+        _ => format!("{}d {:02}h", s / 86400, s % 86400 / 3600),
+    }
+}
+
+// The line printed every so often during a sweep: how far it's got, how fast, how many seeds
+// are still in after each filter and how long the rest should take
+fn progress_line(config: &SweepConfig, progress: &SweepProgress, seeds: u64, seconds: f64) -> String {
+    let rate = seeds as f64 / seconds.max(0.001);
+    let percent = 100.0 * progress.done as f64 / config.range.count.max(1) as f64;
+    let mut line = format!("{} of {} seeds ({:.1}%), {:.0} maps/s, ", progress.done, config.range.count, percent, rate);
+    for (part, name) in config.filter.iter().flat_map(Filter::parts).enumerate() {
+        line += &format!("past {} {}, ", name, progress.survivors(part));
+    }
+    let eta = match rate > 0.0 {
+        true => format_duration((config.range.count - progress.done) as f64 / rate),
+        false => "unknown".to_string(),
+    };
+    line + &format!("kept {}, ETA {}", progress.kept, eta)
+}
+
+/// Generates every seed in the config's range, winnowing the way from_code does, and saves the
+/// maps that are kept and/or writes them to `records`, in seed order. With a `checkpoint` path
+/// the progress is written there every so often and when it finishes, and Ctrl-C stops the sweep
+/// cleanly after writing one, so it can be picked up again with resume_sweep. With `report` a
+/// progress line goes to stderr every few seconds.
+pub fn run_sweep(config: &SweepConfig, threads: usize, records: Option<&RecordWriter>, checkpoint: Option<&str>, report: bool) -> Result<SweepStats, Box<dyn Error>> {
+    let leaderboard = Leaderboard::new(config.top.map_or(0, |(_, size)| size));
+    let rejected = vec![0; config.filter.as_ref().map_or(0, |f| f.parts().len())];
+    continue_sweep(config, SweepProgress { leaderboard, rejected, ..Default::default() }, threads, records, checkpoint, report)
+}
+
+/// Carries on the sweep saved in a checkpoint file, appending to the same records file (after
+/// cutting off anything written since the checkpoint) and checkpointing to the same file
+pub fn resume_sweep(path: &str, threads: usize, report: bool) -> Result<(SweepConfig, SweepStats), Box<dyn Error>> {
+    let checkpoint = Checkpoint::load(path)?;
+    let records = match &checkpoint.records {
+        Some(state) => Some(RecordWriter::reopen(state)?),
+        None => None,
+    };
+    let stats = continue_sweep(&checkpoint.config, checkpoint.progress.clone(), threads, records.as_ref(), Some(path), report)?;
+    Ok((checkpoint.config, stats))
+}
+
+fn continue_sweep(
+    config: &SweepConfig,
+    progress: SweepProgress,
+    threads: usize,
+    records: Option<&RecordWriter>,
+    checkpoint: Option<&str>,
+    report: bool,
+) -> Result<SweepStats, Box<dyn Error>> {
+    let now = Instant::now();
+    let already = progress.done;
+    let remaining = SeedRange { start: config.range.seed(already), count: config.range.count - already };
+    let progress = RefCell::new(progress);
+    let mut last_checkpoint = Instant::now();
+    let mut last_report = Instant::now();
+    let database = match &config.database {
+        Some(_) if config.filter.is_some() => return Err("A results database has every seed in it, so it can't be written with a filter".into()),
+        Some(path) if already == 0 => Some(ResultsWriter::create(path, config.range.start, config.range.count, config.difficulty)?),
+        Some(path) => Some(ResultsWriter::reopen(path, already)?),
+        None => None,
+    };
+    let database = RefCell::new(database);
+    if checkpoint.is_some() {
+        checkpoint::catch_interrupts();
+    }
+
+    let filter = config.filter.clone().map(|f| Arc::new(f) as Arc<dyn Filter>);
+    let result = sweep_seeds(remaining, threads, |seed| {
+        let code = VHRandom::from_seed(seed).get_code();
+        // Which filter threw it away, or None if it didn't generate
+        let map = OverworldMap::from_code(&fcargs!(code, config.difficulty, filter.clone()));
+        Some(map.map_err(|e| e.downcast_ref::<Winnowed>().map(|w| w.filter)))
+    }, |seed, map| {
+        let mut progress = progress.borrow_mut();
+        let map = match map {
+            Ok(map) => map,
+            Err(Some(part)) => { progress.rejected[part] += 1; return Ok(true) },
+            Err(None) => {
+                progress.failed += 1;
+                if let Some(database) = database.borrow_mut().as_mut() {
+                    database.write(&SeedRecord::empty(seed))?;
+                }
+                return Ok(true)
+            },
+        };
+        let route = map.shortest_route();
+        let length = route.first_half + route.last_half;
+        if let Some(database) = database.borrow_mut().as_mut() {
+            database.write(&SeedRecord { route: Some((route.first_half, route.last_half)), ..SeedRecord::from_map(seed, &map) })?;
+        }
+        progress.kept += 1;
+        if progress.best.is_none_or(|(_, best)| length < best) {
+            progress.best = Some((seed, length));
+        }
+        if let Some((metric, _)) = config.top {
+            progress.leaderboard.offer(Entry::new(metric, &map, &route));
+        }
+        if let Some(dir) = &config.save_dir {
+            map.save_map(dir, &map.info().code)?;
+        }
+        if let Some(records) = records {
+            records.write(&map)?;
+        }
+        Ok(true)
+    }, |done| {
+        progress.borrow_mut().done = already + done;
+        let stop = checkpoint::interrupted();
+        if let Some(path) = checkpoint {
+            if stop || done == remaining.count || last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+                let records = records.map(|r| r.state()).transpose()?;
+                if let Some(database) = database.borrow_mut().as_mut() {
+                    database.flush()?;
+                }
+                Checkpoint { config: config.clone(), progress: progress.borrow().clone(), records }.save(path)?;
+                last_checkpoint = Instant::now();
+            }
+        }
+        if report && last_report.elapsed() >= REPORT_INTERVAL && done < remaining.count {
+            eprintln!("{}", progress_line(config, &progress.borrow(), done, now.elapsed().as_secs_f64()));
+            last_report = Instant::now();
+        }
+        Ok(!stop)
+    });
+    if checkpoint.is_some() {
+        checkpoint::release_interrupts();
+    }
+    let finished = result?;
+    if let Some(records) = records {
+        records.flush()?;
+    }
+    if let Some(database) = database.borrow_mut().as_mut() {
+        database.flush()?;
+    }
+    let progress = progress.into_inner();
+    Ok(SweepStats { seeds: progress.done - already, progress, seconds: now.elapsed().as_secs_f64(), finished })
+}
+
+impl SweepStats {
+    /// The line printed at the end of a sweep
+    pub fn summary(&self, config: &SweepConfig) -> String {
+        let winnowed = config.filter.as_ref().map_or(String::new(), |f| format!("filtered by '{}' ", f));
+        let saved = if config.save_dir.is_some() { ", saved " } else { "" };
+        let mut summary = format!("{} maps generated {}{}in {} seconds, {} kept", self.seeds, winnowed, saved, self.seconds, self.progress.kept);
+        if let Some((seed, length)) = self.progress.best {
+            summary += &format!(", best route {} ({})", length, VHRandom::from_seed(seed).get_code());
+        }
+        if !self.finished {
+            summary += &format!(". Stopped after {} of {} seeds", self.progress.done, config.range.count);
+        }
+        summary
+    }
+
+    /// How many seeds each filter threw away, for after the summary. Empty if nothing was.
+    pub fn breakdown(&self, config: &SweepConfig) -> String {
+        let p = &self.progress;
+        let percent = |n: u64| 100.0 * n as f64 / p.done.max(1) as f64;
+        let mut parts: Vec<String> = config.filter.iter().flat_map(Filter::parts).zip(&p.rejected)
+            .map(|(name, &n)| format!("'{}' {} ({:.1}%)", name, n, percent(n)))
+            .collect();
+        if p.failed > 0 {
+            parts.push(format!("{} failed to generate ({:.1}%)", p.failed, percent(p.failed)));
+        }
+        match parts.is_empty() {
+            true => String::new(),
+            false => format!("Rejected by {}", parts.join(", ")),
+        }
+    }
+}
+
+/// How each seed that survives a sweep is written out
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RecordFormat {
+    /// Comma separated, with a header line
+    Csv,
+    /// One JSON object per line
+    JsonLines,
+}
+
+impl RecordFormat {
+    pub fn parse(s: &str) -> Result<RecordFormat, Box<dyn Error>> {
+        match s {
+            "csv" => Ok(RecordFormat::Csv),
+            "jsonl" => Ok(RecordFormat::JsonLines),
+            _ => Err(format!("Unknown record format '{}', it's csv or jsonl", s).into()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RecordFormat::Csv => "csv",
+            RecordFormat::JsonLines => "jsonl",
+        }
+    }
+
+    /// The line to write before any records, if the format has one
+    pub fn header(&self) -> Option<String> {
+        match self {
+            RecordFormat::Csv => {
+                let mut columns = vec!["code".to_string(), "seed".to_string(), "base_map".to_string(), "base_rotation".to_string()];
+                for name in position_names() {
+                    columns.push(format!("{}_x", name));
+                    columns.push(format!("{}_y", name));
+                }
+                columns.extend(["first_half", "last_half", "retries"].map(String::from));
+                Some(columns.join(","))
+            }
+            RecordFormat::JsonLines => None,
+        }
+    }
+
+    /// One line (without the newline) describing a generated map: its code and seed, base map and
+    /// rotation, where the start and every feature are, the two halves of its shortest route and
+    /// how many times generation had to start over
+    pub fn record(&self, map: &OverworldMap) -> Result<String, Box<dyn Error>> {
+        let info = map.info();
+        let positions = SeedRecord::from_map(info.seed, map).positions;
+        let route = map.shortest_route();
+        match self {
+            RecordFormat::Csv => {
+                let mut fields = vec![csv_field(&info.code), info.seed.to_string(), info.base_map.to_string(), info.base_rotation.to_string()];
+                for p in positions.iter() {
+                    match p {
+                        Some((x, y)) => fields.extend([x.to_string(), y.to_string()]),
+                        None => fields.extend([String::new(), String::new()]),
+                    }
+                }
+                fields.extend([route.first_half.to_string(), route.last_half.to_string(), info.retries.to_string()]);
+                Ok(fields.join(","))
+            }
+            RecordFormat::JsonLines => {
+                let position = |p: &Option<(u8, u8)>| p.map_or(Value::Null, |(x, y)| Value::Array(vec![(x as u64).into(), (y as u64).into()]));
+                let mut features = BTreeMap::new();
+                let mut elevators = Vec::new();
+                for (c, p) in INDEX_FEATURES.chars().zip(positions.iter()).skip(1) {
+                    if c == 'e' {
+                        elevators.push(position(p));
+                    } else {
+                        features.insert(c.to_string(), position(p));
+                    }
+                }
+                features.insert("e".to_string(), Value::Array(elevators));
+
+                let mut o = BTreeMap::new();
+                o.insert("code".to_string(), info.code.as_str().into());
+                o.insert("seed".to_string(), (info.seed as u64).into());
+                o.insert("base_map".to_string(), (info.base_map as u64).into());
+                o.insert("base_rotation".to_string(), (info.base_rotation as u64).into());
+                o.insert("start".to_string(), position(&positions[0]));
+                o.insert("features".to_string(), Value::Object(features));
+                o.insert("first_half".to_string(), (route.first_half as u64).into());
+                o.insert("last_half".to_string(), (route.last_half as u64).into());
+                o.insert("retries".to_string(), (info.retries as u64).into());
+                Value::Object(o).to_json()
+            }
+        }
+    }
+}
+
+// Column names for the INDEX_FEATURES slots: start, the legend letters, and e1/e2
+fn position_names() -> Vec<String> {
+    let mut elevators = 0;
+    INDEX_FEATURES.chars().map(|c| match c {
+        '@' => "start".to_string(),
+        'e' => { elevators += 1; format!("e{}", elevators) },
+        c => c.to_string(),
+    }).collect()
+}
+
+// Codes can have commas in them
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        return format!("\"{}\"", s.replace('"', "\"\""));
+    }
+    s.to_string()
+}
+
+/// Where a sweep writes its records. The sweep threads share it, each record goes out as a whole
+/// line.
+pub struct RecordWriter {
+    format: RecordFormat,
+    // None for stdout
+    path: Option<String>,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl RecordWriter {
+    /// Starts writing records to `out`, beginning with the format's header
+    pub fn new(format: RecordFormat, mut out: Box<dyn Write + Send>) -> Result<RecordWriter, Box<dyn Error>> {
+        if let Some(header) = format.header() {
+            writeln!(out, "{}", header)?;
+        }
+        Ok(RecordWriter { format, path: None, out: Mutex::new(out) })
+    }
+
+    /// Writes to a file if a path is given, otherwise stdout
+    pub fn open(format: RecordFormat, path: Option<&str>) -> Result<RecordWriter, Box<dyn Error>> {
+        let out: Box<dyn Write + Send> = match path {
+            Some(path) => Box::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+            None => Box::new(std::io::stdout()),
+        };
+        let mut writer = RecordWriter::new(format, out)?;
+        writer.path = path.map(String::from);
+        Ok(writer)
+    }
+
+    /// Picks a records file back up from a checkpoint: anything past the length it had then is
+    /// cut off, and new records go on the end
+    pub fn reopen(state: &RecordsState) -> Result<RecordWriter, Box<dyn Error>> {
+        let Some(path) = &state.path else {
+            return Ok(RecordWriter { format: state.format, path: None, out: Mutex::new(Box::new(std::io::stdout())) });
+        };
+        if state.length == 0 {
+            return RecordWriter::open(state.format, Some(path));
+        }
+        let mut file = std::fs::OpenOptions::new().write(true).open(path)?;
+        if file.metadata()?.len() < state.length {
+            return Err(format!("{} is shorter than when the checkpoint was written", path).into());
+        }
+        file.set_len(state.length)?;
+        file.seek(std::io::SeekFrom::End(0))?;
+        Ok(RecordWriter { format: state.format, path: Some(path.clone()), out: Mutex::new(Box::new(std::io::BufWriter::new(file))) })
+    }
+
+    /// Flushes what's been written so far and says where it's got to, for a checkpoint
+    pub fn state(&self) -> Result<RecordsState, Box<dyn Error>> {
+        self.flush()?;
+        let length = match &self.path {
+            Some(path) => std::fs::metadata(path)?.len(),
+            None => 0,
+        };
+        Ok(RecordsState { format: self.format, path: self.path.clone(), length })
+    }
+
+    pub fn write(&self, map: &OverworldMap) -> Result<bool, Box<dyn Error>> {
+        let line = self.format.record(map)?;
+        writeln!(self.out.lock().unwrap(), "{}", line)?;
+        Ok(true)
+    }
+
+    pub fn flush(&self) -> Result<bool, Box<dyn Error>> {
+        self.out.lock().unwrap().flush()?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::basemaps::{tests::synthetic_base_file, BaseMapSet, BaseMapSource};
+    use crate::json;
+    use crate::map::{_FCargs, Difficulty};
+    use crate::fcargs;
+
+    #[test]
+    fn sweeps_in_order_on_any_thread_count() {
+        let range = SeedRange::new(u32::MAX - 5000, 3 * CHUNK_SIZE + 7).unwrap();
+        let run = |threads| {
+            let mut seen = Vec::new();
+            sweep_seeds(range, threads, |seed| (seed % 3 == 0).then_some(seed / 3), |seed, third| {
+                assert!(seed / 3 == third);
+                seen.push(seed);
+                Ok(true)
+            }, |_| Ok(true)).unwrap();
+            seen
+        };
+        let one = run(1);
+        assert!(one == run(3) && one == run(16));
+        // Wraps past u32::MAX
+        let expected: Vec<u32> = (0..range.count).map(|i| range.seed(i)).filter(|s| s % 3 == 0).collect();
+        assert!(one == expected && one.iter().any(|&s| s < 10));
+        assert!(SeedRange::new(0, (1 << 32) + 1).is_err());
+
+        // A failing emit stops the sweep
+        let mut emitted = 0;
+        let result = sweep_seeds(range, 2, Some, |_, _| { emitted += 1; if emitted == 10 { Err("full".into()) } else { Ok(true) } }, |_| Ok(true));
+        assert!(result.is_err() && emitted == 10);
+
+        // And done can stop it after a chunk
+        let mut last = 0;
+        let finished = sweep_seeds(range, 2, Some, |_, _| Ok(true), |done| { last = done; Ok(done < 2 * CHUNK_SIZE) }).unwrap();
+        assert!(!finished && last == 2 * CHUNK_SIZE);
+    }
+
+    #[test]
+    fn writes_records() {
+        let set = Arc::new(BaseMapSet::load(&BaseMapSource::Memory(vec![synthetic_base_file(); 5])).unwrap());
+        let map = OverworldMap::from_code(&_FCargs { base_maps: Some(set), ..fcargs!("B,BBBBBBBB", Difficulty::Hard) }).unwrap();
+        let route = map.shortest_route();
+
+        let header = RecordFormat::Csv.header().unwrap();
+        let row = RecordFormat::Csv.record(&map).unwrap();
+        assert!(row.starts_with("\"B,BBBBBBBB\","));
+        // The quoted comma aside, there's a field for every column. The shop isn't placed on Hard.
+        assert!(header.split(',').count() == row.split(',').count() - 1);
+        assert!(row.contains(",,,") && row.ends_with(&format!(",{},{},{}", route.first_half, route.last_half, map.info().retries)));
+
+        let line = json::parse(&RecordFormat::JsonLines.record(&map).unwrap()).unwrap();
+        assert!(line.get("code").unwrap().as_str() == Some("B,BBBBBBBB"));
+        assert!(line.get("features").unwrap().get("e").unwrap().as_array().unwrap().len() == 2);
+        assert!(line.get("features").unwrap().get("$") == Some(&Value::Null));
+        assert!(line.get("last_half").unwrap().as_u64() == Some(route.last_half as u64));
+        assert!(RecordFormat::parse("xml").is_err());
+    }
+
+    #[test]
+    fn shards_cover_the_range() {
+        let range = SeedRange::new(u32::MAX - 100, 1 << 32).unwrap();
+        let shards: Vec<SeedRange> = (1..=7).map(|k| range.shard(k, 7).unwrap()).collect();
+        assert!(shards[0].start == range.start && shards.iter().map(|s| s.count).sum::<u64>() == range.count);
+        for pair in shards.windows(2) {
+            assert!(pair[0].seed(pair[0].count) == pair[1].start);
+        }
+        assert!(range.shard(0, 7).is_err() && range.shard(8, 7).is_err());
+        assert!(SeedRange::new(0, 3).unwrap().shard(1, 4).is_err());
+    }
+
+    #[test]
+    fn reports_progress() {
+        let filter = Expression::parse_filter("map4,under50").unwrap();
+        let config = SweepConfig { range: SeedRange::new(0, 1000).unwrap(), difficulty: Difficulty::Easy, filter, save_dir: None, top: None, database: None };
+        let progress = SweepProgress { done: 500, kept: 20, best: None, rejected: vec![400, 78], failed: 2, leaderboard: Leaderboard::default() };
+        assert!(progress.survivors(0) == 98 && progress.survivors(1) == 20);
+        let line = progress_line(&config, &progress, 500, 10.0);
+        assert!(line == "500 of 1000 seeds (50.0%), 50 maps/s, past base == 4 98, past total < 50 20, kept 20, ETA 10s");
+        let stats = SweepStats { seeds: 500, progress, seconds: 10.0, finished: false };
+        assert!(stats.breakdown(&config) == "Rejected by 'base == 4' 400 (80.0%), 'total < 50' 78 (15.6%), 2 failed to generate (0.4%)");
+        assert!(format_duration(3725.0) == "1h 02m" && format_duration(200000.0) == "2d 07h");
+    }
+
+    #[test]
+    fn reopens_records_where_the_checkpoint_left_them() {
+        let set = Arc::new(BaseMapSet::load(&BaseMapSource::Memory(vec![synthetic_base_file(); 5])).unwrap());
+        let map = |code: &str| OverworldMap::from_code(&_FCargs { base_maps: Some(set.clone()), ..fcargs!(code, Difficulty::Easy) }).unwrap();
+        let path = std::env::temp_dir().join(format!("vh_records_{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let writer = RecordWriter::open(RecordFormat::Csv, Some(path)).unwrap();
+        writer.write(&map("BBBBBBBBBB")).unwrap();
+        let state = writer.state().unwrap();
+        // Written after the checkpoint, so a resume drops it
+        writer.write(&map("CCCCCCCCCC")).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+
+        let writer = RecordWriter::reopen(&state).unwrap();
+        writer.write(&map("DDDDDDDDDD")).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+        let text = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let codes: Vec<&str> = text.lines().map(|l| l.split(',').next().unwrap()).collect();
+        assert!(codes == ["code", "BBBBBBBBBB", "DDDDDDDDDD"]);
+    }
+}